@@ -161,6 +161,8 @@ impl eframe::App for Dashboard {
                             &mut self.state,
                             &mut self.duplicate_scanner_state,
                             self.settings_state.media_groups.clone(),
+                            self.settings_state.scan_options.clone(),
+                            &self.settings_state.match_profiles,
                             &mut self.notification_bar,
                         );
                     }
@@ -32,8 +32,11 @@ pub struct ApplicationState {
     pub zoom_factor: f32,
 
     // Filter in duplicates_table.rs
-    pub filter_unknown: bool,
+    pub filter_custom: bool,
     pub filter_musicbrainz: bool,
+    pub filter_sources: bool, // Tag types and differing values of other tags, the '#' keys
+    // Auto-mark keeps the copy with the largest cover first
+    pub prefer_artwork: bool,
 }
 
 impl ApplicationState {
@@ -48,8 +51,10 @@ impl ApplicationState {
             memory_window_open: false,
             zoom_factor: 1.0,
 
-            filter_unknown: true,
+            filter_custom: true,
             filter_musicbrainz: true,
+            filter_sources: true,
+            prefer_artwork: false,
 
         }
     }
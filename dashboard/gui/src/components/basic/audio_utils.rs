@@ -0,0 +1,459 @@
+use anyhow::anyhow;
+use rustfft::{num_complex::Complex, FftPlanner};
+use std::{collections::HashMap, fs::File, path::Path};
+use symphonia::core::{
+    audio::SampleBuffer,
+    codecs::{DecoderOptions, CODEC_TYPE_NULL},
+    errors::Error as SymphoniaError,
+    formats::FormatOptions,
+    io::MediaSourceStream,
+    meta::MetadataOptions,
+    probe::Hint,
+};
+
+const FP_SAMPLE_RATE: u32 = 11025;
+const FP_FRAME_SIZE: usize = 4096;
+const FP_HOP_SIZE: usize = FP_FRAME_SIZE / 3;
+const FP_MIN_FREQ: f32 = 28.0;
+const FP_MAX_FREQ: f32 = 3520.0;
+const FP_MIN_OVERLAP: usize = 20; // About 2.5 seconds of audio
+const INTEGRITY_TOLERANCE_MS: u64 = 1000;
+const ENVELOPE_BLOCK_SIZE: usize = 256;
+
+/// Mono samples of a decoded audio file
+pub struct DecodedAudio {
+    pub samples: Vec<f32>,
+    pub sample_rate: u32,
+    pub decode_errors: usize, // Packets which could not be decoded and were skipped
+}
+
+/// Summary of decoding an audio stream
+pub struct DecodeInfo {
+    pub sample_rate: u32,
+    pub frames: u64,                  // Decoded frames (samples per channel)
+    pub expected_frames: Option<u64>, // Frames announced by the stream header
+    pub decode_errors: usize,         // Packets which could not be decoded and were skipped
+    pub stream_error: Option<String>, // Error which stopped reading the stream before its end
+    pub stopped: bool,                // Decoding was stopped by 'is_stopped' before the end
+}
+
+/// Decode the first audio track of a file into mono samples.
+///
+/// Decoding stops after 'max_seconds' of audio, 0 decodes the whole file. Packets with decode
+/// errors are skipped and counted.
+pub fn decode_audio(path: &Path, max_seconds: u32) -> anyhow::Result<DecodedAudio> {
+    let mut samples = Vec::new();
+    let info = decode_mono(path, max_seconds, || false, |sample| samples.push(sample))?;
+    if let Some(e) = info.stream_error {
+        return Err(anyhow!(e));
+    }
+    Ok(DecodedAudio { samples, sample_rate: info.sample_rate, decode_errors: info.decode_errors })
+}
+
+/// Decode the first audio track of a file and pass each mono sample to 'sink'.
+///
+/// Decoding stops after 'max_seconds' of audio, 0 decodes the whole file. Packets with decode
+/// errors are skipped and counted. An error of the container stops decoding and is returned
+/// in the 'stream_error', with the samples decoded so far. 'is_stopped' is checked before
+/// each packet, decoding ends early with 'stopped' set if it returns true.
+pub fn decode_mono(path: &Path, max_seconds: u32, is_stopped: impl Fn() -> bool, mut sink: impl FnMut(f32)) -> anyhow::Result<DecodeInfo> {
+    let source = MediaSourceStream::new(Box::new(File::open(path)?), Default::default());
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(extension);
+    }
+    let probed = symphonia::default::get_probe().format(&hint, source, &FormatOptions::default(), &MetadataOptions::default())?;
+    let mut format = probed.format;
+
+    let track = format
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or_else(|| anyhow!("No audio track found"))?;
+    let track_id = track.id;
+    let sample_rate = track.codec_params.sample_rate.unwrap_or(44100);
+    let mut decoder = symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+
+    let max_frames = max_seconds as u64 * sample_rate as u64;
+    let mut info = DecodeInfo {
+        sample_rate,
+        frames: 0,
+        expected_frames: track.codec_params.n_frames,
+        decode_errors: 0,
+        stream_error: None,
+        stopped: false,
+    };
+    let mut buffer: Option<SampleBuffer<f32>> = None;
+    loop {
+        if is_stopped() {
+            info.stopped = true;
+            break;
+        }
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(SymphoniaError::ResetRequired) => break,
+            Err(e) => {
+                info.stream_error = Some(e.to_string());
+                break;
+            }
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            Err(SymphoniaError::DecodeError(_)) => {
+                info.decode_errors += 1;
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let spec = *decoded.spec();
+        let channels = spec.channels.count().max(1);
+        if buffer.as_ref().map_or(true, |b| b.capacity() < decoded.capacity() * channels) {
+            buffer = Some(SampleBuffer::new(decoded.capacity() as u64, spec));
+        }
+        let buffer = buffer.as_mut().unwrap();
+        buffer.copy_interleaved_ref(decoded);
+        for frame in buffer.samples().chunks(channels) {
+            sink(frame.iter().sum::<f32>() / channels as f32);
+            info.frames += 1;
+            if max_frames > 0 && info.frames >= max_frames {
+                return Ok(info);
+            }
+        }
+    }
+    Ok(info)
+}
+
+/// Decode a whole audio file and describe its problems: decode errors, a truncated stream and
+/// a decoded duration which differs from the duration of the tags ('tag_duration_ms').
+/// Returns 'OK' for an intact file, None if decoding was stopped by 'is_stopped'.
+pub fn get_integrity(path: &Path, tag_duration_ms: Option<u64>, is_stopped: impl Fn() -> bool) -> Option<String> {
+    let info = match decode_mono(path, 0, is_stopped, |_| ()) {
+        Ok(info) if info.stopped => return None,
+        Ok(info) => info,
+        Err(e) => return Some(format!("Undecodable: {}", e)),
+    };
+    let decoded_ms = info.frames * 1000 / info.sample_rate.max(1) as u64;
+
+    let mut problems = Vec::new();
+    if info.decode_errors > 0 {
+        problems.push(format!("{} decode errors", info.decode_errors));
+    }
+    if let Some(e) = &info.stream_error {
+        problems.push(format!("Stream error after {:.1} s: {}", decoded_ms as f32 / 1000.0, e));
+    }
+    if let Some(expected) = info.expected_frames {
+        // Allow 1% for encoder delay and padding
+        if info.frames + expected / 100 < expected {
+            let expected_ms = expected * 1000 / info.sample_rate.max(1) as u64;
+            problems.push(format!("Truncated: {:.1} of {:.1} s", decoded_ms as f32 / 1000.0, expected_ms as f32 / 1000.0));
+        }
+    }
+    if let Some(tag_ms) = tag_duration_ms.filter(|ms| *ms > 0) {
+        if decoded_ms.abs_diff(tag_ms) > INTEGRITY_TOLERANCE_MS.max(tag_ms / 50) {
+            problems.push(format!("Duration {:.1} s, tags say {:.1} s", decoded_ms as f32 / 1000.0, tag_ms as f32 / 1000.0));
+        }
+    }
+
+    if problems.is_empty() {
+        Some("OK".to_string())
+    } else {
+        Some(problems.join(", "))
+    }
+}
+
+/// Amplitude envelope of an audio file: the peak of each interval
+pub struct Envelope {
+    pub peaks: Vec<f32>,
+    pub interval_ms: u32,
+    pub duration_ms: u64,
+}
+
+/// Decode a whole audio file and return the peak amplitude of each 'interval_ms'
+pub fn get_envelope(path: &Path, interval_ms: u32) -> anyhow::Result<Envelope> {
+    // The sample rate is only known after decoding, so keep the peaks of small blocks first
+    let mut blocks: Vec<f32> = Vec::new();
+    let mut peak = 0.0f32;
+    let mut count = 0;
+    let info = decode_mono(path, 0, || false, |sample| {
+        peak = peak.max(sample.abs());
+        count += 1;
+        if count == ENVELOPE_BLOCK_SIZE {
+            blocks.push(peak);
+            peak = 0.0;
+            count = 0;
+        }
+    })?;
+    if count > 0 {
+        blocks.push(peak);
+    }
+
+    let samples_per_interval = (info.sample_rate as u64 * interval_ms as u64 / 1000).max(1);
+    let mut peaks: Vec<f32> = Vec::new();
+    for (i, block) in blocks.iter().enumerate() {
+        let interval = (i as u64 * ENVELOPE_BLOCK_SIZE as u64 / samples_per_interval) as usize;
+        if interval >= peaks.len() {
+            peaks.resize(interval + 1, 0.0);
+        }
+        peaks[interval] = peaks[interval].max(*block);
+    }
+    let duration_ms = info.frames * 1000 / info.sample_rate.max(1) as u64;
+    Ok(Envelope { peaks, interval_ms, duration_ms })
+}
+
+/// Calculate a chromaprint-style fingerprint of the first 'max_seconds' of an audio file.
+///
+/// The audio is resampled to 11025 Hz and split into overlapping frames. Each frame is folded
+/// into 12 pitch classes (chroma), and each 32-bit sub-fingerprint encodes how the chroma
+/// changes over time and between neighboring pitch classes. This makes the fingerprint
+/// independent of format, bitrate and volume.
+pub fn get_audio_fingerprint(path: &Path, max_seconds: u32) -> anyhow::Result<Vec<u32>> {
+    let audio = decode_audio(path, max_seconds)?;
+    let samples = resample(&audio.samples, audio.sample_rate, FP_SAMPLE_RATE);
+    if samples.len() < FP_FRAME_SIZE {
+        return Err(anyhow!("Audio too short for a fingerprint"));
+    }
+
+    let chroma = chroma_frames(&samples);
+
+    // Smooth over 3 frames to reduce the influence of encoding artifacts
+    let smoothed: Vec<[f32; 12]> = (1..chroma.len().saturating_sub(1))
+        .map(|t| {
+            let mut bins = [0.0; 12];
+            for (i, bin) in bins.iter_mut().enumerate() {
+                *bin = chroma[t - 1][i] + chroma[t][i] + chroma[t + 1][i];
+            }
+            bins
+        })
+        .collect();
+
+    let fingerprint = smoothed.windows(2).map(|w| sub_fingerprint(&w[0], &w[1])).collect();
+    Ok(fingerprint)
+}
+
+/// Compare two fingerprints and return the similarity in percent (0..=100).
+///
+/// The best alignment is searched with the offsets of identical sub-fingerprints, so tracks
+/// with different leading silence still match. Returns 0 if the fingerprints don't overlap.
+pub fn compare_fingerprints(a: &[u32], b: &[u32]) -> u32 {
+    let mut positions: HashMap<u32, Vec<usize>> = HashMap::new();
+    for (j, value) in b.iter().enumerate() {
+        positions.entry(*value).or_default().push(j);
+    }
+    let mut offsets: HashMap<isize, usize> = HashMap::new();
+    for (i, value) in a.iter().enumerate() {
+        for j in positions.get(value).into_iter().flatten() {
+            *offsets.entry(i as isize - *j as isize).or_default() += 1;
+        }
+    }
+    let mut offsets: Vec<(isize, usize)> = offsets.into_iter().collect();
+    offsets.sort_by_key(|(offset, count)| (std::cmp::Reverse(*count), *offset));
+    if offsets.is_empty() {
+        offsets.push((0, 0));
+    }
+
+    offsets.iter().take(3).map(|(offset, _)| similarity_at(a, b, *offset)).max().unwrap_or(0)
+}
+
+/// Similarity in percent of 'a' and 'b', with a[i] aligned to b[i - offset]
+fn similarity_at(a: &[u32], b: &[u32], offset: isize) -> u32 {
+    let start = offset.max(0) as usize;
+    let end = (b.len() as isize + offset).min(a.len() as isize);
+    if end - (start as isize) < FP_MIN_OVERLAP as isize {
+        return 0;
+    }
+    let end = end as usize;
+    let errors: u32 = (start..end).map(|i| (a[i] ^ b[(i as isize - offset) as usize]).count_ones()).sum();
+    let bits = 32 * (end - start) as u32;
+
+    // Unrelated audio differs in about half of the bits, so scale 50%..0% errors to 0..100
+    let error_rate = errors as f32 / bits as f32;
+    ((1.0 - 2.0 * error_rate).max(0.0) * 100.0).round() as u32
+}
+
+/// Linear interpolation resampling, good enough for the fingerprint frequency range. When
+/// downsampling, the input is low-pass filtered first, so frequencies above the new Nyquist
+/// frequency don't fold into the fingerprint and all sample rates give the same result.
+fn resample(samples: &[f32], from: u32, to: u32) -> Vec<f32> {
+    if from == to || samples.is_empty() {
+        return samples.to_vec();
+    }
+    let step = from as f64 / to as f64;
+    let kernel = if step > 1.0 { low_pass_kernel(0.45 / step, (4.0 * step).ceil() as usize) } else { vec![1.0] };
+    let half = kernel.len() / 2;
+
+    // The filtered input sample at 'index', only computed where it is interpolated
+    let filtered = |index: usize| -> f32 {
+        let first = index.saturating_sub(half);
+        let last = (index + half + 1).min(samples.len());
+        samples[first..last].iter().zip(kernel[first + half - index..].iter()).map(|(s, k)| s * k).sum()
+    };
+
+    let len = (samples.len() as f64 / step) as usize;
+    (0..len)
+        .map(|i| {
+            let pos = i as f64 * step;
+            let index = pos as usize;
+            let fraction = (pos - index as f64) as f32;
+            let next = if index + 1 < samples.len() { index + 1 } else { index };
+            filtered(index) * (1.0 - fraction) + filtered(next) * fraction
+        })
+        .collect()
+}
+
+/// Hann windowed sinc low-pass filter with 2 * 'half' + 1 taps. The 'cutoff' is relative to
+/// the sample rate. The taps sum up to 1.
+fn low_pass_kernel(cutoff: f64, half: usize) -> Vec<f32> {
+    let kernel: Vec<f64> = (0..2 * half + 1)
+        .map(|i| {
+            let x = i as f64 - half as f64;
+            let sinc = if x == 0.0 { 1.0 } else { (2.0 * std::f64::consts::PI * cutoff * x).sin() / (std::f64::consts::PI * x) / (2.0 * cutoff) };
+            let window = 0.5 + 0.5 * (std::f64::consts::PI * x / (half + 1) as f64).cos();
+            sinc * window
+        })
+        .collect();
+    let sum: f64 = kernel.iter().sum();
+    kernel.iter().map(|k| (k / sum) as f32).collect()
+}
+
+/// Normalized energy of the 12 pitch classes for each frame
+fn chroma_frames(samples: &[f32]) -> Vec<[f32; 12]> {
+    let fft = FftPlanner::<f32>::new().plan_fft_forward(FP_FRAME_SIZE);
+    let window: Vec<f32> = (0..FP_FRAME_SIZE)
+        .map(|i| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / (FP_FRAME_SIZE - 1) as f32).cos())
+        .collect();
+
+    // Pitch class of every FFT bin inside the frequency range
+    let bin_width = FP_SAMPLE_RATE as f32 / FP_FRAME_SIZE as f32;
+    let pitch_classes: Vec<Option<usize>> = (0..FP_FRAME_SIZE / 2)
+        .map(|bin| {
+            let freq = bin as f32 * bin_width;
+            if !(FP_MIN_FREQ..=FP_MAX_FREQ).contains(&freq) {
+                return None;
+            }
+            let note = 12.0 * (freq / 27.5).log2();
+            Some((note.round() as usize) % 12)
+        })
+        .collect();
+
+    let mut frames = Vec::new();
+    let mut buffer = vec![Complex::new(0.0, 0.0); FP_FRAME_SIZE];
+    let mut start = 0;
+    while start + FP_FRAME_SIZE <= samples.len() {
+        for (i, value) in buffer.iter_mut().enumerate() {
+            *value = Complex::new(samples[start + i] * window[i], 0.0);
+        }
+        fft.process(&mut buffer);
+
+        let mut chroma = [0.0f32; 12];
+        for (bin, pitch_class) in pitch_classes.iter().enumerate() {
+            if let Some(pitch_class) = pitch_class {
+                chroma[*pitch_class] += buffer[bin].norm_sqr();
+            }
+        }
+        let norm = chroma.iter().map(|c| c * c).sum::<f32>().sqrt();
+        if norm > 1e-6 {
+            chroma.iter_mut().for_each(|c| *c /= norm);
+        }
+        frames.push(chroma);
+        start += FP_HOP_SIZE;
+    }
+    frames
+}
+
+/// Encode the chroma of a frame and its predecessor into 32 bits: 12 bits for the change over
+/// time, 12 bits for the gradient to the next pitch class and 8 bits for the gradient to the
+/// fifth above.
+fn sub_fingerprint(previous: &[f32; 12], current: &[f32; 12]) -> u32 {
+    let mut bits = 0u32;
+    for i in 0..12 {
+        bits = (bits << 1) | (current[i] > previous[i]) as u32;
+    }
+    for i in 0..12 {
+        bits = (bits << 1) | (current[i] > current[(i + 1) % 12]) as u32;
+    }
+    for i in 0..8 {
+        bits = (bits << 1) | (current[i] > current[(i + 7) % 12]) as u32;
+    }
+    bits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(freq: f32, sample_rate: u32, seconds: f32) -> Vec<f32> {
+        let len = (sample_rate as f32 * seconds) as usize;
+        (0..len).map(|i| (2.0 * std::f32::consts::PI * freq * i as f32 / sample_rate as f32).sin()).collect()
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        // Without the edges, where the filter has fewer input samples
+        let samples = &samples[samples.len() / 10..samples.len() * 9 / 10];
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn resample_keeps_low_frequencies() {
+        for from in [44100, 48000, 96000] {
+            let samples = resample(&sine(440.0, from, 1.0), from, FP_SAMPLE_RATE);
+            assert!(samples.len().abs_diff(FP_SAMPLE_RATE as usize) <= 1);
+            assert!((rms(&samples) - 0.707).abs() < 0.02, "{} Hz: {}", from, rms(&samples));
+        }
+    }
+
+    #[test]
+    fn resample_removes_frequencies_above_nyquist() {
+        for from in [44100, 48000, 96000] {
+            let samples = resample(&sine(8000.0, from, 1.0), from, FP_SAMPLE_RATE);
+            assert!(rms(&samples) < 0.02, "{} Hz: {}", from, rms(&samples));
+        }
+    }
+
+    /// A mono 16 bit WAV file of a sine
+    fn wav_file(name: &str, sample_rate: u32, seconds: f32) -> std::path::PathBuf {
+        let samples: Vec<i16> = sine(440.0, sample_rate, seconds).iter().map(|s| (s * 16000.0) as i16).collect();
+        let data_len = samples.len() as u32 * 2;
+        let mut data = b"RIFF".to_vec();
+        data.extend_from_slice(&(36 + data_len).to_le_bytes());
+        data.extend_from_slice(b"WAVEfmt ");
+        data.extend_from_slice(&[16, 0, 0, 0, 1, 0, 1, 0]);
+        data.extend_from_slice(&sample_rate.to_le_bytes());
+        data.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+        data.extend_from_slice(&[2, 0, 16, 0]);
+        data.extend_from_slice(b"data");
+        data.extend_from_slice(&data_len.to_le_bytes());
+        samples.iter().for_each(|s| data.extend_from_slice(&s.to_le_bytes()));
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, data).unwrap();
+        path
+    }
+
+    #[test]
+    fn integrity_of_an_intact_file() {
+        let path = wav_file("audio_utils_intact.wav", 8000, 2.0);
+        let integrity = get_integrity(&path, Some(2000), || false);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(integrity.as_deref(), Some("OK"));
+    }
+
+    #[test]
+    fn integrity_check_can_be_stopped() {
+        let path = wav_file("audio_utils_stopped.wav", 8000, 2.0);
+        let integrity = get_integrity(&path, None, || true);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(integrity, None);
+    }
+
+    #[test]
+    fn low_pass_kernel_is_normalized() {
+        let kernel = low_pass_kernel(0.1, 20);
+        assert_eq!(kernel.len(), 41);
+        assert!((kernel.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+        assert_eq!(kernel[0], kernel[40]);
+    }
+}
@@ -23,12 +23,13 @@ pub fn get_header_checksum(path: &Path) -> Result<isize, Error> {
     Ok(checksum)
 }
 
+/// Returns the uppercase extension of the file name including the dot, i.e. '.MP3'
 pub fn get_extension(path: &str) -> String {
-    let extension = match path.rfind('.') {
-        Some(idx) => (&path[idx..].to_uppercase()).to_owned(),
+    let name = Path::new(path).file_name().and_then(|n| n.to_str()).unwrap_or(path);
+    match name.rfind('.') {
+        Some(idx) => name[idx..].to_uppercase(),
         None => String::new(),
-    };
-    extension
+    }
 }
 
 pub fn compute_file_checksum(file: &Path) -> Result<String, Error> {
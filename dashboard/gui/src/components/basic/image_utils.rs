@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::path::Path;
+
+use anyhow::{Error, Result};
+use image::imageops::FilterType;
+use image::{ImageReader, RgbaImage};
+
+/// Calculates the perceptual difference hash (dHash) of an image.
+///
+/// The image is scaled down to 9x8 gray pixels. Each bit of the hash tells if a pixel is
+/// brighter than its right neighbour, so resized or re-encoded copies get the same or a
+/// very similar hash.
+pub fn get_image_hash(path: &Path) -> Result<u64, Error> {
+    let image = ImageReader::open(path)?.with_guessed_format()?.decode()?;
+    let small = image.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+
+    let mut hash: u64 = 0;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if small.get_pixel(x, y)[0] < small.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+    Ok(hash)
+}
+
+/// Number of differing bits of two image hashes. 0 = identical, 64 = completely different
+pub fn hamming_distance(hash1: u64, hash2: u64) -> u32 {
+    (hash1 ^ hash2).count_ones()
+}
+
+/// Similarity in percent for the distance of two image hashes
+pub fn similarity(distance: u32) -> u32 {
+    100 - distance * 100 / 64
+}
+
+/// Returns the width and height of an encoded image without decoding the pixels
+pub fn get_image_dimensions(data: &[u8]) -> Result<(u32, u32), Error> {
+    Ok(ImageReader::new(Cursor::new(data)).with_guessed_format()?.into_dimensions()?)
+}
+
+/// Decode an encoded image and scale it to fit into 'size' x 'size' pixels
+pub fn get_thumbnail(data: &[u8], size: u32) -> Result<RgbaImage, Error> {
+    let image = ImageReader::new(Cursor::new(data)).with_guessed_format()?.decode()?;
+    Ok(image.thumbnail(size, size).to_rgba8())
+}
+
+/// BK-tree of image hashes. Finds all hashes within a hamming distance without comparing
+/// every pair of hashes.
+#[derive(Default)]
+pub struct HashTree {
+    nodes: Vec<HashNode>,
+}
+
+struct HashNode {
+    hash: u64,
+    items: Vec<usize>,             // Items with this hash
+    children: HashMap<u32, usize>, // Child node by its distance to this node
+}
+
+impl HashTree {
+    pub fn new() -> Self {
+        Self { nodes: Vec::new() }
+    }
+
+    /// Add an item with the given hash
+    pub fn insert(&mut self, hash: u64, item: usize) {
+        let mut idx = 0;
+        while idx < self.nodes.len() {
+            let distance = hamming_distance(self.nodes[idx].hash, hash);
+            if distance == 0 {
+                self.nodes[idx].items.push(item);
+                return;
+            }
+            match self.nodes[idx].children.get(&distance) {
+                Some(child) => idx = *child,
+                None => {
+                    let child = self.nodes.len();
+                    self.nodes[idx].children.insert(distance, child);
+                    break;
+                }
+            }
+        }
+        self.nodes.push(HashNode { hash, items: vec![item], children: HashMap::new() });
+    }
+
+    /// Returns the items whose hash differs in at most 'max_distance' bits
+    pub fn find(&self, hash: u64, max_distance: u32) -> Vec<usize> {
+        let mut found = Vec::new();
+        let mut stack = if self.nodes.is_empty() { vec![] } else { vec![0] };
+        while let Some(idx) = stack.pop() {
+            let node = &self.nodes[idx];
+            let distance = hamming_distance(node.hash, hash);
+            if distance <= max_distance {
+                found.extend(node.items.iter().copied());
+            }
+            // Only subtrees within the distance range can hold matches
+            let range = distance.saturating_sub(max_distance)..=distance + max_distance;
+            stack.extend(node.children.iter().filter(|(d, _)| range.contains(d)).map(|(_, child)| *child));
+        }
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Simple xorshift, so the hashes are spread over all bits without a rand dependency
+    fn hashes(count: usize) -> Vec<u64> {
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        (0..count)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state
+            })
+            .collect()
+    }
+
+    #[test]
+    fn tree_finds_the_same_items_as_brute_force() {
+        let mut all = hashes(300);
+        // Near copies and exact copies of some hashes
+        for i in 0..50 {
+            all.push(all[i] ^ (1 << (i % 64)) ^ (1 << ((i * 7) % 64)));
+            all.push(all[i]);
+        }
+
+        let mut tree = HashTree::new();
+        for (item, hash) in all.iter().enumerate() {
+            tree.insert(*hash, item);
+        }
+
+        for max_distance in [0, 2, 10, 28] {
+            for hash in all.iter() {
+                let mut found = tree.find(*hash, max_distance);
+                found.sort();
+                let expected: Vec<usize> =
+                    (0..all.len()).filter(|i| hamming_distance(all[*i], *hash) <= max_distance).collect();
+                assert_eq!(found, expected, "distance {}", max_distance);
+            }
+        }
+    }
+
+    #[test]
+    fn empty_tree_finds_nothing() {
+        assert!(HashTree::new().find(0, 64).is_empty());
+    }
+}
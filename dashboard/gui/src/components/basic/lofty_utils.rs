@@ -3,14 +3,28 @@ use std::path::Path;
 use std::collections::HashMap;
 use lofty::file::AudioFile;
 use lofty::file::TaggedFileExt;
+use lofty::picture::{Picture, PictureType};
 use lofty::probe::Probe;
-use crate::components::basic::string_utils::*;
-use anyhow::{Result, Error, anyhow};
+use lofty::config::WriteOptions;
+use lofty::tag::{ItemKey, ItemValue, Tag, TagExt, TagItem, TagType};
+use anyhow::{anyhow, Result, Error};
+use crate::components::basic::image_utils::get_image_dimensions;
+use crate::components::basic::tag_schema::{canonical_key, is_custom, is_multi_valued};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
 
-fn unwrap(s: Option<&String>) -> String {
-    s.unwrap_or(&"".to_string()).to_string()
-}
+/// Separator of the values of multi-valued items, i.e. multiple artists
+pub const VALUE_SEPARATOR: &str = "; ";
 
+/// Read the tags and audio properties of a file.
+///
+/// All tags of the file are merged, the primary tag first. The keys are the canonical names
+/// of the tag schema, so the same field has the same key in all formats. Each key gets a
+/// '<key>#src' entry with the tag types holding it. If another tag holds a different value,
+/// it is added as '<key>#<tag type>'. Multiple items with the same key are joined with
+/// VALUE_SEPARATOR, binary items and pictures are summarized. The cover is described by 'Cover' (dimensions,
+/// format and size), 'CoverPixels' and 'CoverHash'. The properties are also read for files
+/// without a tag.
 pub fn get_audio_tags(file: &Path) -> Result<HashMap<String, String>, Error> {
     let mut map: HashMap<String, String> = HashMap::new();
     // Insert the file Path as PATH entry
@@ -19,88 +33,188 @@ pub fn get_audio_tags(file: &Path) -> Result<HashMap<String, String>, Error> {
     let probe = Probe::open(file)?;
     let tagged_file = probe.read()?;
 
-    tagged_file.primary_tag();
-    // Extract the Tag from the file if any
-    if let Some(tag) = tagged_file.primary_tag() {
-        for item in tag.items().into_iter() {
-            let value = item.value().text().unwrap_or("");
-            let key = format!("{:?}", item.key());
-            map.insert(key, value.to_string());
+    // Primary tag first, so its values win
+    let primary = tagged_file.primary_tag_type();
+    let mut tags: Vec<&Tag> = tagged_file.tags().iter().collect();
+    tags.sort_by_key(|tag| tag.tag_type() != primary);
+
+    for tag in tags {
+        let tag_type = format!("{:?}", tag.tag_type());
+
+        // Collect multiple items with the same key
+        let mut values: Vec<(String, Vec<String>)> = Vec::new();
+        for item in tag.items() {
+            let key = canonical_key(item.key());
+            let value = match item.value() {
+                ItemValue::Text(text) | ItemValue::Locator(text) => text.clone(),
+                ItemValue::Binary(data) => format!("<binary, {} bytes>", data.len()),
+            };
+            match values.iter_mut().find(|(k, _)| *k == key) {
+                Some((_, list)) => list.push(value),
+                None => values.push((key, vec![value])),
+            }
+        }
+        let pictures: Vec<String> = tag.pictures().iter().map(picture_summary).collect();
+        if !pictures.is_empty() {
+            values.push(("Pictures".to_string(), pictures));
         }
 
-        let properties = tagged_file.properties();
-        map.insert("Duration".to_string(), properties.duration().as_secs().to_string());
-        map.insert("SampleRate".to_string(), properties.sample_rate().unwrap_or(0).to_string());
-        map.insert("Channels".to_string(), properties.channels().unwrap_or(0).to_string());
-        map.insert("ChannelMask".to_string(), format!("{:?}", properties.channel_mask().unwrap_or_default()));
-        map.insert("AudioBitrate".to_string(), properties.audio_bitrate().unwrap_or(0).to_string());
-        map.insert("OverallBitrate".to_string(), properties.overall_bitrate().unwrap_or(0).to_string());
-        map.insert("BitDepth".to_string(), properties.bit_depth().unwrap_or(0).to_string());
+        for (key, list) in values {
+            let value = list.join(VALUE_SEPARATOR);
+            match map.get(&key) {
+                None => {
+                    map.insert(key.clone(), value);
+                    map.insert(format!("{}#src", key), tag_type.clone());
+                }
+                Some(existing) => {
+                    if *existing != value {
+                        map.insert(format!("{}#{}", key, tag_type), value);
+                    }
+                    let src = map.entry(format!("{}#src", key)).or_default();
+                    src.push_str(&format!(", {}", tag_type));
+                }
+            }
+        }
     }
+
+    if let Some(cover) = find_cover(tagged_file.tags()) {
+        let size = cover.data().len().div_ceil(1024);
+        let mime = cover.mime_type().map_or("unknown", |m| m.as_str());
+        match get_image_dimensions(cover.data()) {
+            Ok((width, height)) => {
+                map.insert("Cover".to_string(), format!("{}x{} {} {} KB", width, height, mime, size));
+                map.insert("CoverPixels".to_string(), (width as u64 * height as u64).to_string());
+            }
+            Err(_) => {
+                map.insert("Cover".to_string(), format!("unreadable {} {} KB", mime, size));
+                map.insert("CoverPixels".to_string(), "0".to_string());
+            }
+        }
+        let mut hasher = DefaultHasher::new();
+        hasher.write(cover.data());
+        map.insert("CoverHash".to_string(), format!("{:016x}", hasher.finish()));
+    }
+
+    let properties = tagged_file.properties();
+    map.insert("Duration".to_string(), properties.duration().as_secs().to_string());
+    map.insert("DurationMs".to_string(), properties.duration().as_millis().to_string());
+    map.insert("SampleRate".to_string(), properties.sample_rate().unwrap_or(0).to_string());
+    map.insert("Channels".to_string(), properties.channels().unwrap_or(0).to_string());
+    map.insert("ChannelMask".to_string(), format!("{:?}", properties.channel_mask().unwrap_or_default()));
+    map.insert("AudioBitrate".to_string(), properties.audio_bitrate().unwrap_or(0).to_string());
+    map.insert("OverallBitrate".to_string(), properties.overall_bitrate().unwrap_or(0).to_string());
+    map.insert("BitDepth".to_string(), properties.bit_depth().unwrap_or(0).to_string());
+    map.insert("Format".to_string(), format!("{:?}", tagged_file.file_type()));
     Ok(map)
 }
 
-pub fn get_short_audio_key(file: &Path) -> Result<String, Error> {
-    match get_audio_tags(file) {
-        Ok(map) => {
-            // Get Track
-            let track = normalize_option(map.get("TrackTitle"));
-            if track.is_empty() {
-                return Err(anyhow!("TrackTitle is empty"));
-            }
-            // Get Duration
-            let duration = unwrap(map.get("Duration"));
-            if duration.is_empty() {
-                return Err(anyhow!("Duration=0"));
+/// Write text items into all tags of a file, so the merged tags of 'get_audio_tags' show the
+/// new values. An empty value removes the item. Values of multi-valued fields (i.e. artists or
+/// genres) joined with VALUE_SEPARATOR are written as multiple items, other values are written
+/// as they are. The primary tag is created if the file has none. A field the primary tag
+/// can't hold is an error, other tags which can't hold it only lose the old value.
+pub fn write_audio_tags(file: &Path, items: &[(ItemKey, String)]) -> Result<(), Error> {
+    let mut tagged_file = Probe::open(file)?.read()?;
+    let primary = tagged_file.primary_tag_type();
+    if tagged_file.primary_tag().is_none() {
+        tagged_file.insert_tag(Tag::new(primary));
+    }
+
+    let tag_types: Vec<TagType> = tagged_file.tags().iter().map(|tag| tag.tag_type()).collect();
+    for tag_type in tag_types {
+        let tag = tagged_file.tag_mut(tag_type).ok_or_else(|| anyhow!("Could not create a {:?} tag", tag_type))?;
+        for (key, value) in items {
+            tag.remove_key(key);
+            let parts: Vec<&str> = if is_multi_valued(key) { value.split(VALUE_SEPARATOR).collect() } else { vec![value] };
+            for part in parts.into_iter().filter(|part| !part.is_empty()) {
+                if !tag.push(TagItem::new(key.clone(), ItemValue::Text(part.to_string()))) && tag_type == primary {
+                    return Err(anyhow!("{:?} is not supported by {:?} tags", key, tag_type));
+                }
             }
+        }
+        tag.save_to_path(file, WriteOptions::default())?;
+    }
+    Ok(())
+}
+
+/// Returns the tag types 'write_audio_tags' writes to, the primary tag first
+pub fn get_tag_types(file: &Path) -> Result<Vec<String>, Error> {
+    let tagged_file = Probe::open(file)?.read()?;
+    let primary = tagged_file.primary_tag_type();
+    let mut tag_types: Vec<TagType> = tagged_file.tags().iter().map(|tag| tag.tag_type()).filter(|t| *t != primary).collect();
+    tag_types.insert(0, primary);
+    Ok(tag_types.iter().map(|t| format!("{:?}", t)).collect())
+}
+
+/// Returns the encoded front cover of a file, or its first picture if there is no front cover
+pub fn get_cover(file: &Path) -> Result<Option<Vec<u8>>, Error> {
+    let tagged_file = Probe::open(file)?.read()?;
+    Ok(find_cover(tagged_file.tags()).map(|picture| picture.data().to_vec()))
+}
 
-            Ok(format!("{}{}", duration, track))
-        },
-        Err(e) => Err(e)
+/// The front cover of any tag, or the first picture
+fn find_cover(tags: &[Tag]) -> Option<&Picture> {
+    let pictures = || tags.iter().flat_map(|tag| tag.pictures().iter());
+    pictures().find(|p| p.pic_type() == PictureType::CoverFront).or_else(|| pictures().next())
+}
+
+/// Summary of a picture, i.e. 'CoverFront image/jpeg 45 KB'
+fn picture_summary(picture: &Picture) -> String {
+    let mime = picture.mime_type().map_or("unknown", |m| m.as_str());
+    let mut summary = format!("{:?} {} {} KB", picture.pic_type(), mime, picture.data().len().div_ceil(1024));
+    if let Some(description) = picture.description().filter(|d| !d.is_empty()) {
+        summary.push_str(&format!(" '{}'", description));
     }
+    summary
 }
 
-pub fn get_audio_key(file: &Path) -> Result<String, Error> {
-    match get_audio_tags(file) {
-        Ok(map) => {
-            // Get Artist
-            let mut artist = unwrap(map.get("AlbumArtist"));
-            if artist.is_empty() {
-                artist = unwrap(map.get("TrackArtist"));
-            }
-            // Get Album
-            let mut album = unwrap(map.get("AlbumTitle"));
-            if album.is_empty() {
-                album = unwrap(map.get("OriginalAlbumTitle"));
-            }
-            // Get Track
-            let track = normalize_option(map.get("TrackTitle"));
-            // Get Duration
-            let mut duration = unwrap(map.get("Duration"));
-            if duration.is_empty() {
-                duration = "0".to_string();
-            }
+/// Returns a quality label i.e. 'Lossless FLAC 24 bit / 96 kHz, 2650 kbps' and a rank which
+/// sorts better copies last, from the properties read by 'get_audio_tags'.
+///
+/// Returns None if the map has no audio properties.
+pub fn get_quality(map: &HashMap<String, String>) -> Option<(String, String)> {
+    let format = map.get("Format")?;
+    let number = |key: &str| map.get(key).and_then(|v| v.parse::<u32>().ok()).unwrap_or(0);
+    let (bitrate, sample_rate, bit_depth) = (number("AudioBitrate"), number("SampleRate"), number("BitDepth"));
+
+    // MP4 files only have a bit depth if they hold ALAC
+    let lossless = match format.as_str() {
+        "Flac" | "Ape" | "WavPack" | "Wav" | "Aiff" => true,
+        "Mp4" => bit_depth > 0,
+        _ => false,
+    };
+    let codec = match format.as_str() {
+        "Mpeg" => "MP3",
+        "Mp4" if lossless => "ALAC",
+        "Mp4" => "AAC",
+        other => other,
+    };
 
-            Ok(format!("{}{}{}{}", duration, artist, album, track))
-        },
-        Err(e) => Err(e)
+    let mut label = format!("{} {}", if lossless { "Lossless" } else { "Lossy" }, codec.to_uppercase());
+    if bit_depth > 0 {
+        label.push_str(&format!(" {} bit /", bit_depth));
     }
+    label.push_str(&format!(" {:.1} kHz, {} kbps", sample_rate as f32 / 1000.0, bitrate));
+
+    let rank = format!("{}:{:03}:{:07}:{:06}", lossless as u8, bit_depth, sample_rate, bitrate);
+    Some((label, rank))
 }
 
-// Remove PATH and 'Unknown' tags
-pub fn filter_tags(map: &HashMap<String, String>, unknown: bool, musicbrainz: bool) -> Vec<&String> {
-    fn custom_filter(item: &String, unknown: bool, musicbrainz: bool) -> bool {
+// Remove PATH, custom tags and the '#' source entries
+pub fn filter_tags(map: &HashMap<String, String>, custom: bool, musicbrainz: bool, sources: bool) -> Vec<&String> {
+    fn custom_filter(item: &String, custom: bool, musicbrainz: bool, sources: bool) -> bool {
         let mut flt: bool = true;
         if item.to_uppercase().contains("PATH") {flt = false;}
-        if unknown {
-            if item.to_uppercase().contains("UNKNOWN") {flt = false;}
+        if custom {
+            if is_custom(item) {flt = false;}
         }
         if musicbrainz {
             if item.to_uppercase().contains("MUSICBRAINZ") {flt = false;}
         }
+        if sources && item.contains('#') {flt = false;}
         flt
     }
-    let mut filtered: Vec<&String> = map.keys().filter(|i| {custom_filter(i, unknown, musicbrainz)}).collect();
+    let mut filtered: Vec<&String> = map.keys().filter(|i| {custom_filter(i, custom, musicbrainz, sources)}).collect();
     filtered.sort();
     filtered
 }
@@ -0,0 +1,345 @@
+use std::{
+    fs::File,
+    io::{Error, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+const HEADER_SIZE: u64 = 4096; // Holds the first two frames of MPEG audio
+
+/// Bitrates in kbps of the MPEG audio bitrate indices 1 to 14
+const MPEG1_BITRATES: [[u32; 14]; 3] = [
+    [32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448], // Layer I
+    [32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384],    // Layer II
+    [32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320],     // Layer III
+];
+const MPEG2_BITRATES: [[u32; 14]; 2] = [
+    [32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256], // Layer I
+    [8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],      // Layer II and III
+];
+/// Sample rates of MPEG 1, the rates of MPEG 2 are half, of MPEG 2.5 a quarter
+const MPEG1_SAMPLE_RATES: [u32; 3] = [44100, 48000, 32000];
+
+/// A file format detected by its content.
+///
+/// The first extension is the canonical one, used to find the MediaGroup of the format.
+/// An empty extension list marks files which are never media files (i.e. AppleDouble).
+#[derive(Debug)]
+pub struct FileKind {
+    pub name: &'static str,
+    pub extensions: &'static [&'static str],
+}
+
+impl FileKind {
+    pub fn is_ignored(&self) -> bool {
+        self.extensions.is_empty()
+    }
+
+    /// Returns true if the extension (i.e. '.JPG') is a valid one for this format
+    pub fn has_extension(&self, extension: &str) -> bool {
+        self.extensions.iter().any(|e| e.eq_ignore_ascii_case(extension))
+    }
+
+    /// The canonical extension in the form used by MediaType, i.e. '.JPG'
+    pub fn canonical_extension(&self) -> String {
+        self.extensions.first().unwrap_or(&"").to_uppercase()
+    }
+}
+
+const APPLE_DOUBLE: FileKind = FileKind { name: "AppleDouble", extensions: &[] };
+const MP3: FileKind = FileKind { name: "MP3", extensions: &[".mp3", ".mp2", ".mpga"] };
+const AAC: FileKind = FileKind { name: "AAC", extensions: &[".aac"] };
+const FLAC: FileKind = FileKind { name: "FLAC", extensions: &[".flac"] };
+const OGG: FileKind = FileKind { name: "Ogg", extensions: &[".ogg", ".oga", ".opus", ".mogg", ".spx", ".ogv"] };
+const WAV: FileKind = FileKind { name: "WAV", extensions: &[".wav", ".rf64"] };
+const RF64: FileKind = FileKind { name: "RF64", extensions: &[".rf64", ".wav"] };
+const AVI: FileKind = FileKind { name: "AVI", extensions: &[".avi"] };
+const WEBP: FileKind = FileKind { name: "WebP", extensions: &[".webp"] };
+const AIFF: FileKind = FileKind { name: "AIFF", extensions: &[".aiff", ".aif", ".aifc"] };
+const SVX: FileKind = FileKind { name: "8SVX", extensions: &[".8svx"] };
+const APE: FileKind = FileKind { name: "Monkey's Audio", extensions: &[".ape"] };
+const WAVPACK: FileKind = FileKind { name: "WavPack", extensions: &[".wv"] };
+const MPC: FileKind = FileKind { name: "Musepack", extensions: &[".mpc"] };
+const TTA: FileKind = FileKind { name: "True Audio", extensions: &[".tta"] };
+const AMR: FileKind = FileKind { name: "AMR", extensions: &[".amr"] };
+const AMR_WB: FileKind = FileKind { name: "AMR-WB", extensions: &[".awb", ".amr"] };
+const AU: FileKind = FileKind { name: "Sun Audio", extensions: &[".au", ".snd"] };
+const ASF: FileKind = FileKind { name: "ASF", extensions: &[".wma", ".wmv", ".asf"] };
+const REAL: FileKind = FileKind { name: "RealMedia", extensions: &[".rm", ".ra", ".rmvb"] };
+const VOC: FileKind = FileKind { name: "Creative Voice", extensions: &[".voc"] };
+const M4A: FileKind = FileKind { name: "MPEG-4 Audio", extensions: &[".m4a", ".m4b", ".m4p", ".mp4"] };
+const MP4: FileKind = FileKind { name: "MPEG-4", extensions: &[".mp4", ".m4v", ".m4a", ".m4b", ".m4p", ".mov", ".3gp", ".3g2", ".movpkg"] };
+const QUICKTIME: FileKind = FileKind { name: "QuickTime", extensions: &[".mov", ".mp4", ".movpkg"] };
+const HEIF: FileKind = FileKind { name: "HEIF", extensions: &[".heic", ".heif", ".avif"] };
+const MATROSKA: FileKind = FileKind { name: "Matroska", extensions: &[".mkv", ".webm", ".mka", ".mk3d"] };
+const MPEG_PS: FileKind = FileKind { name: "MPEG-PS", extensions: &[".vob", ".mpg", ".mpeg"] };
+const JPEG: FileKind = FileKind { name: "JPEG", extensions: &[".jpg", ".jpeg", ".jpe", ".jfif"] };
+const PNG: FileKind = FileKind { name: "PNG", extensions: &[".png"] };
+const GIF: FileKind = FileKind { name: "GIF", extensions: &[".gif"] };
+const BMP: FileKind = FileKind { name: "BMP", extensions: &[".bmp", ".dib"] };
+const TIFF: FileKind = FileKind { name: "TIFF", extensions: &[".tif", ".tiff", ".dng", ".nef", ".cr2", ".arw"] };
+const ICO: FileKind = FileKind { name: "ICO", extensions: &[".ico"] };
+const PDF: FileKind = FileKind { name: "PDF", extensions: &[".pdf"] };
+const RTF: FileKind = FileKind { name: "RTF", extensions: &[".rtf"] };
+const ZIP: FileKind = FileKind {
+    name: "ZIP",
+    extensions: &[".zip", ".docx", ".xlsx", ".pptx", ".pptm", ".ppsx", ".odt", ".ods", ".odp", ".jar", ".epub", ".apk"],
+};
+const OLE2: FileKind = FileKind { name: "OLE2", extensions: &[".doc", ".xls", ".ppt", ".sdw", ".msi"] };
+const RAR: FileKind = FileKind { name: "RAR", extensions: &[".rar"] };
+const SEVEN_ZIP: FileKind = FileKind { name: "7-Zip", extensions: &[".7z"] };
+const GZIP: FileKind = FileKind { name: "GZIP", extensions: &[".gz", ".tgz"] };
+const TAR: FileKind = FileKind { name: "TAR", extensions: &[".tar"] };
+
+/// Detect the format of a file from its first bytes.
+///
+/// Returns None if the content has no known signature (i.e. plain text). A leading ID3v2 tag
+/// is skipped, so FLAC or AAC files with an ID3v2 tag are detected correctly.
+pub fn detect_file_kind(path: &Path) -> Result<Option<&'static FileKind>, Error> {
+    let mut file = File::open(path)?;
+    let mut header = read_header(&mut file)?;
+
+    if header.starts_with(b"ID3") && header.len() >= 10 {
+        let mut offset = 10 + syncsafe(&header[6..10]);
+        if header[5] & 0x10 != 0 {
+            offset += 10; // Footer present
+        }
+        file.seek(SeekFrom::Start(offset))?;
+        header = read_header(&mut file)?;
+        return Ok(match detect_audio_frame(&header) {
+            Some(kind) => Some(kind),
+            None => Some(&MP3), // Only an ID3v2 tag
+        });
+    }
+
+    Ok(detect_kind(&header))
+}
+
+fn read_header(file: &mut File) -> Result<Vec<u8>, Error> {
+    let mut header = Vec::with_capacity(HEADER_SIZE as usize);
+    file.take(HEADER_SIZE).read_to_end(&mut header)?;
+    Ok(header)
+}
+
+/// Decode a 28-bit syncsafe integer as used in ID3v2 headers
+fn syncsafe(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |size, b| (size << 7) | (*b & 0x7f) as u64)
+}
+
+/// Detect FLAC or a stream of MPEG audio or ADTS frames. A frame sync alone is too weak for
+/// binary data, so the header must be valid and the next frame must follow it.
+fn detect_audio_frame(h: &[u8]) -> Option<&'static FileKind> {
+    if h.starts_with(b"fLaC") {
+        return Some(&FLAC);
+    }
+    if let Some(length) = adts_frame_length(h) {
+        // The second frame must have the same MPEG version and sampling frequency
+        let next = h.get(length..).unwrap_or(&[]);
+        return adts_frame_length(next).filter(|_| next[1] == h[1] && next[2] >> 2 == h[2] >> 2).map(|_| &AAC);
+    }
+    if let Some(length) = mpeg_frame_length(h) {
+        // The second frame must have the same version, layer and sample rate
+        let next = h.get(length..).unwrap_or(&[]);
+        return mpeg_frame_length(next).filter(|_| next[1] & 0xfe == h[1] & 0xfe && next[2] & 0x0c == h[2] & 0x0c).map(|_| &MP3);
+    }
+    None
+}
+
+/// The length of the ADTS frame (AAC) starting at 'h', None if it is no valid frame header
+fn adts_frame_length(h: &[u8]) -> Option<usize> {
+    if h.len() < 7 || h[0] != 0xff || h[1] & 0xf6 != 0xf0 {
+        return None; // Sync word with layer 0
+    }
+    let frequency = (h[2] >> 2) & 0x0f;
+    let length = ((h[3] as usize & 0x03) << 11) | ((h[4] as usize) << 3) | (h[5] as usize >> 5);
+    (frequency < 13 && length >= 7).then_some(length)
+}
+
+/// The length of the MPEG audio frame (MP1-3) starting at 'h', None if it is no valid frame
+/// header. Free format frames (bitrate index 0) have no known length and are not detected.
+fn mpeg_frame_length(h: &[u8]) -> Option<usize> {
+    if h.len() < 4 || h[0] != 0xff || h[1] & 0xe0 != 0xe0 {
+        return None;
+    }
+    let version = (h[1] >> 3) & 0x03; // 0 = MPEG 2.5, 1 = reserved, 2 = MPEG 2, 3 = MPEG 1
+    let layer = (h[1] >> 1) & 0x03; // 0 = reserved, 1 = Layer III, 2 = Layer II, 3 = Layer I
+    let bitrate_index = (h[2] >> 4) as usize;
+    let sample_rate_index = ((h[2] >> 2) & 0x03) as usize;
+    if version == 1 || layer == 0 || bitrate_index == 0 || bitrate_index == 0x0f || sample_rate_index == 3 {
+        return None;
+    }
+
+    let bitrate = match (version, layer) {
+        (3, layer) => MPEG1_BITRATES[3 - layer as usize][bitrate_index - 1],
+        (_, 3) => MPEG2_BITRATES[0][bitrate_index - 1],
+        _ => MPEG2_BITRATES[1][bitrate_index - 1],
+    } * 1000;
+    let sample_rate = MPEG1_SAMPLE_RATES[sample_rate_index] >> (3 - version).min(2);
+    let padding = ((h[2] >> 1) & 0x01) as u32;
+    let length = match (version, layer) {
+        (_, 3) => (12 * bitrate / sample_rate + padding) * 4,
+        (3, _) | (_, 2) => 144 * bitrate / sample_rate + padding,
+        _ => 72 * bitrate / sample_rate + padding, // Layer III of MPEG 2 and 2.5
+    };
+    Some(length as usize)
+}
+
+fn detect_kind(h: &[u8]) -> Option<&'static FileKind> {
+    let at = |offset: usize, magic: &[u8]| h.len() >= offset + magic.len() && &h[offset..offset + magic.len()] == magic;
+
+    if at(0, &[0x00, 0x05, 0x16, 0x07]) || at(0, &[0x00, 0x05, 0x16, 0x00]) {
+        return Some(&APPLE_DOUBLE);
+    }
+    if let Some(kind) = detect_audio_frame(h) {
+        return Some(kind);
+    }
+
+    // Container formats with a sub type
+    if at(0, b"RIFF") {
+        if at(8, b"WAVE") {
+            return Some(&WAV);
+        } else if at(8, b"AVI ") {
+            return Some(&AVI);
+        } else if at(8, b"WEBP") {
+            return Some(&WEBP);
+        }
+        return None;
+    }
+    if at(0, b"FORM") {
+        if at(8, b"AIFF") || at(8, b"AIFC") {
+            return Some(&AIFF);
+        } else if at(8, b"8SVX") {
+            return Some(&SVX);
+        }
+        return None;
+    }
+    if at(4, b"ftyp") {
+        if at(8, b"M4A ") || at(8, b"M4B ") || at(8, b"M4P ") {
+            return Some(&M4A);
+        } else if at(8, b"qt  ") {
+            return Some(&QUICKTIME);
+        } else if at(8, b"heic") || at(8, b"heix") || at(8, b"mif1") || at(8, b"avif") {
+            return Some(&HEIF);
+        }
+        return Some(&MP4);
+    }
+
+    let signatures: [(usize, &[u8], &'static FileKind); 30] = [
+        (0, b"OggS", &OGG),
+        (0, b"RF64", &RF64),
+        (0, b"MAC ", &APE),
+        (0, b"wvpk", &WAVPACK),
+        (0, b"MPCK", &MPC),
+        (0, b"MP+", &MPC),
+        (0, b"TTA1", &TTA),
+        (0, b"#!AMR-WB\n", &AMR_WB),
+        (0, b"#!AMR\n", &AMR),
+        (0, b".snd", &AU),
+        (0, &[0x30, 0x26, 0xb2, 0x75, 0x8e, 0x66, 0xcf, 0x11], &ASF),
+        (0, b".RMF", &REAL),
+        (0, b".ra\xfd", &REAL),
+        (0, b"Creative Voice File", &VOC),
+        (0, &[0x1a, 0x45, 0xdf, 0xa3], &MATROSKA),
+        (0, &[0x00, 0x00, 0x01, 0xba], &MPEG_PS),
+        (0, &[0xff, 0xd8, 0xff], &JPEG),
+        (0, &[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a], &PNG),
+        (0, b"GIF87a", &GIF),
+        (0, b"GIF89a", &GIF),
+        (0, b"II*\0", &TIFF),
+        (0, b"MM\0*", &TIFF),
+        (0, b"%PDF-", &PDF),
+        (0, b"{\\rtf", &RTF),
+        (0, b"PK\x03\x04", &ZIP),
+        (0, &[0xd0, 0xcf, 0x11, 0xe0, 0xa1, 0xb1, 0x1a, 0xe1], &OLE2),
+        (0, b"Rar!\x1a\x07", &RAR),
+        (0, &[b'7', b'z', 0xbc, 0xaf, 0x27, 0x1c], &SEVEN_ZIP),
+        (0, &[0x1f, 0x8b], &GZIP),
+        (257, b"ustar", &TAR),
+    ];
+    for (offset, magic, kind) in signatures.iter() {
+        if at(*offset, magic) {
+            return Some(kind);
+        }
+    }
+
+    // Weak signatures, checked with some additional header fields
+    if at(0, b"BM") && at(6, &[0, 0, 0, 0]) {
+        return Some(&BMP);
+    }
+    if at(0, &[0x00, 0x00, 0x01, 0x00]) && h.len() > 4 && h[4] != 0 {
+        return Some(&ICO);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two frames of the given header, 'length' bytes apart
+    fn frames(header: &[u8], length: usize) -> Vec<u8> {
+        let mut data = header.to_vec();
+        data.resize(length, 0);
+        data.extend_from_slice(header);
+        data.resize(length * 2, 0);
+        data
+    }
+
+    fn name(h: &[u8]) -> Option<&'static str> {
+        detect_kind(h).map(|kind| kind.name)
+    }
+
+    #[test]
+    fn mpeg_frame_lengths() {
+        assert_eq!(mpeg_frame_length(&[0xff, 0xfb, 0x90, 0x00]), Some(417)); // MPEG 1 Layer III, 128 kbps, 44.1 kHz
+        assert_eq!(mpeg_frame_length(&[0xff, 0xfb, 0x92, 0x00]), Some(418)); // Padded
+        assert_eq!(mpeg_frame_length(&[0xff, 0xf3, 0x80, 0x00]), Some(208)); // MPEG 2 Layer III, 64 kbps, 22.05 kHz
+        assert_eq!(mpeg_frame_length(&[0xff, 0xff, 0x90, 0x00]), Some(312)); // MPEG 1 Layer I, 288 kbps, 44.1 kHz
+    }
+
+    #[test]
+    fn invalid_mpeg_headers_are_rejected() {
+        assert_eq!(mpeg_frame_length(&[0xff, 0xfb, 0xf0, 0x00]), None); // Bitrate index 15
+        assert_eq!(mpeg_frame_length(&[0xff, 0xfb, 0x9c, 0x00]), None); // Sample rate index 3
+        assert_eq!(mpeg_frame_length(&[0xff, 0xf9, 0x90, 0x00]), None); // Layer 0
+        assert_eq!(mpeg_frame_length(&[0xff, 0xeb, 0x90, 0x00]), None); // Reserved version
+    }
+
+    #[test]
+    fn mp3_needs_a_second_frame() {
+        assert_eq!(name(&frames(&[0xff, 0xfb, 0x90, 0x00], 417)), Some("MP3"));
+        let mut single = frames(&[0xff, 0xfb, 0x90, 0x00], 417);
+        single[417] = 0;
+        assert_eq!(name(&single), None);
+        assert_eq!(name(&[0xff, 0xe3, 0x12, 0x34, 0x56, 0x78]), None);
+    }
+
+    #[test]
+    fn adts_needs_a_second_frame() {
+        // AAC LC, 44.1 kHz, stereo, 200 bytes
+        let header = [0xff, 0xf1, 0x50, 0x80, 0x19, 0x1f, 0xfc];
+        assert_eq!(name(&frames(&header, 200)), Some("AAC"));
+        assert_eq!(name(&header), None);
+    }
+
+    #[test]
+    fn signatures() {
+        assert_eq!(name(b"fLaC\0\0\0\x22"), Some("FLAC"));
+        assert_eq!(name(b"RIFF\0\0\0\0WAVEfmt "), Some("WAV"));
+        assert_eq!(name(b"\0\0\0\x20ftypM4A \0\0\0\0"), Some("MPEG-4 Audio"));
+        assert_eq!(name(&[0xff, 0xd8, 0xff, 0xe0]), Some("JPEG"));
+        assert_eq!(name(b"BM\x36\0\0\0\0\0\0\0"), Some("BMP"));
+        assert_eq!(name(b"plain text"), None);
+    }
+
+    #[test]
+    fn id3v2_tag_is_skipped() {
+        let mut data = b"ID3\x04\0\0\0\0\0\x0a".to_vec();
+        data.extend_from_slice(&[0; 10]);
+        data.extend_from_slice(b"fLaC\0\0\0\x22");
+        let path = std::env::temp_dir().join("magic_utils_id3v2_flac.mp3");
+        std::fs::write(&path, &data).unwrap();
+        let kind = detect_file_kind(&path).unwrap().map(|kind| kind.name);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(kind, Some("FLAC"));
+    }
+}
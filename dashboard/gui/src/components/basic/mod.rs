@@ -3,5 +3,10 @@
 pub mod font;
 pub mod string_utils;
 pub mod file_utils;
+pub mod image_utils;
 pub mod lofty_utils;
+pub mod magic_utils;
 
+pub mod audio_utils;
+pub mod payload_utils;
+pub mod tag_schema;
@@ -0,0 +1,329 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    fs::File,
+    hash::Hasher,
+    io::{Error, ErrorKind, Read, Seek, SeekFrom},
+    ops::Range,
+    path::Path,
+};
+
+const ID3V1_SIZE: u64 = 128;
+const ID3V1_ENHANCED_SIZE: u64 = 227;
+const APE_FOOTER_SIZE: u64 = 32;
+
+/// Returns the byte ranges of a file which hold the audio stream.
+///
+/// Tag blocks are skipped: ID3v2 at the start, ID3v1, Lyrics3v2 and APEv2 at the end, the
+/// FLAC metadata blocks, the comment packet of Ogg streams, all MP4 boxes except 'mdat' and
+/// all RIFF/AIFF chunks except the sample data. Other formats are returned as a whole without
+/// the tags.
+pub fn get_audio_payload(path: &Path) -> Result<Vec<Range<u64>>, Error> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+
+    let start = skip_id3v2(&mut file, len)?;
+    let end = strip_trailing_tags(&mut file, start, len)?;
+
+    let mut magic = [0u8; 4];
+    if end - start >= 12 {
+        read_at(&mut file, start, &mut magic)?;
+    }
+
+    let ranges = match &magic {
+        b"fLaC" => flac_payload(&mut file, start, end)?,
+        b"OggS" => ogg_payload(&mut file, start, end)?,
+        b"RIFF" => chunk_payload(&mut file, start, end, b"WAVE", b"data", false)?,
+        b"FORM" => chunk_payload(&mut file, start, end, b"AIFF", b"SSND", true)?,
+        _ if end - start >= 8 => {
+            let mut ftyp = [0u8; 4];
+            read_at(&mut file, start + 4, &mut ftyp)?;
+            if &ftyp == b"ftyp" {
+                mp4_payload(&mut file, start, end)?
+            } else {
+                vec![Range { start, end }]
+            }
+        }
+        _ => vec![Range { start, end }],
+    };
+    Ok(merge_ranges(ranges))
+}
+
+/// Returns the total length of the ranges
+pub fn payload_length(ranges: &[Range<u64>]) -> u64 {
+    ranges.iter().map(|r| r.end - r.start).sum()
+}
+
+/// Hash the bytes of the given ranges of a file
+pub fn compute_payload_checksum(path: &Path, ranges: &[Range<u64>]) -> Result<String, Error> {
+    let mut file = File::open(path)?;
+    let mut hasher = DefaultHasher::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    for range in ranges.iter() {
+        file.seek(SeekFrom::Start(range.start))?;
+        let mut remaining = range.end - range.start;
+        while remaining > 0 {
+            let size = remaining.min(buffer.len() as u64) as usize;
+            file.read_exact(&mut buffer[..size])?;
+            hasher.write(&buffer[..size]);
+            remaining -= size as u64;
+        }
+    }
+    Ok(format!("{:016x}", hasher.finish()))
+}
+
+fn read_at(file: &mut File, offset: u64, buffer: &mut [u8]) -> Result<(), Error> {
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(buffer)
+}
+
+fn truncated() -> Error {
+    Error::new(ErrorKind::UnexpectedEof, "Truncated audio container")
+}
+
+/// Skip all ID3v2 tags at the start of the file. Returns the offset of the first byte after them
+fn skip_id3v2(file: &mut File, len: u64) -> Result<u64, Error> {
+    let mut offset = 0;
+    let mut header = [0u8; 10];
+    while offset + 10 <= len {
+        read_at(file, offset, &mut header)?;
+        if &header[0..3] != b"ID3" {
+            break;
+        }
+        let size = header[6..10].iter().fold(0u64, |size, b| (size << 7) | (*b & 0x7f) as u64);
+        offset += 10 + size;
+        if header[5] & 0x10 != 0 {
+            offset += 10; // Footer present
+        }
+    }
+    Ok(offset.min(len))
+}
+
+/// Strip the ID3v1, Lyrics3v2 and APEv2 tags at the end of the file. Returns the new end offset
+fn strip_trailing_tags(file: &mut File, start: u64, mut end: u64) -> Result<u64, Error> {
+    loop {
+        let previous = end;
+
+        let mut id = [0u8; 4];
+        if end - start >= ID3V1_SIZE {
+            read_at(file, end - ID3V1_SIZE, &mut id)?;
+            if &id[0..3] == b"TAG" {
+                end -= ID3V1_SIZE;
+                if end - start >= ID3V1_ENHANCED_SIZE {
+                    read_at(file, end - ID3V1_ENHANCED_SIZE, &mut id)?;
+                    if &id == b"TAG+" {
+                        end -= ID3V1_ENHANCED_SIZE;
+                    }
+                }
+            }
+        }
+
+        let mut footer = [0u8; 15];
+        if end - start >= footer.len() as u64 {
+            read_at(file, end - footer.len() as u64, &mut footer)?;
+            if &footer[6..15] == b"LYRICS200" {
+                let size: u64 = std::str::from_utf8(&footer[0..6]).ok().and_then(|s| s.parse().ok()).unwrap_or(0);
+                end = end.saturating_sub(size + footer.len() as u64).max(start);
+            }
+        }
+
+        let mut footer = [0u8; APE_FOOTER_SIZE as usize];
+        if end - start >= APE_FOOTER_SIZE {
+            read_at(file, end - APE_FOOTER_SIZE, &mut footer)?;
+            if &footer[0..8] == b"APETAGEX" {
+                let size = u32::from_le_bytes(footer[12..16].try_into().unwrap()) as u64;
+                let flags = u32::from_le_bytes(footer[20..24].try_into().unwrap());
+                let header = if flags & 0x8000_0000 != 0 { APE_FOOTER_SIZE } else { 0 };
+                end = end.saturating_sub(size + header).max(start);
+            }
+        }
+
+        if end == previous {
+            return Ok(end);
+        }
+    }
+}
+
+/// FLAC: the audio frames follow the last metadata block
+fn flac_payload(file: &mut File, start: u64, end: u64) -> Result<Vec<Range<u64>>, Error> {
+    let mut offset = start + 4;
+    let mut header = [0u8; 4];
+    loop {
+        if offset + 4 > end {
+            return Err(truncated());
+        }
+        read_at(file, offset, &mut header)?;
+        let size = u32::from_be_bytes([0, header[1], header[2], header[3]]) as u64;
+        offset += 4 + size;
+        if header[0] & 0x80 != 0 {
+            break; // Last metadata block
+        }
+    }
+    Ok(vec![Range { start: offset.min(end), end }])
+}
+
+/// Ogg: the packets of all logical streams, without the second packet (the comment header)
+fn ogg_payload(file: &mut File, start: u64, end: u64) -> Result<Vec<Range<u64>>, Error> {
+    let mut ranges = Vec::new();
+    let mut packet_index: HashMap<u32, usize> = HashMap::new(); // Current packet of each stream
+    let mut offset = start;
+    let mut header = [0u8; 27];
+    let mut lacing = [0u8; 255];
+
+    while offset + 27 <= end {
+        read_at(file, offset, &mut header)?;
+        if &header[0..4] != b"OggS" {
+            return Err(Error::new(ErrorKind::InvalidData, "Lost Ogg page sync"));
+        }
+        let serial = u32::from_le_bytes(header[14..18].try_into().unwrap());
+        let segments = header[26] as usize;
+        read_at(file, offset + 27, &mut lacing[..segments])?;
+
+        let index = packet_index.entry(serial).or_insert(0);
+        let mut data = offset + 27 + segments as u64;
+        for size in lacing[..segments].iter() {
+            if *index != 1 {
+                ranges.push(data..data + *size as u64);
+            }
+            data += *size as u64;
+            if *size < 255 {
+                *index += 1; // Packet ends in this segment
+            }
+        }
+        if data > end {
+            return Err(truncated());
+        }
+        offset = data;
+    }
+    Ok(ranges)
+}
+
+/// MP4: the content of the top level 'mdat' boxes
+fn mp4_payload(file: &mut File, start: u64, end: u64) -> Result<Vec<Range<u64>>, Error> {
+    let mut ranges = Vec::new();
+    let mut offset = start;
+    let mut header = [0u8; 16];
+    while offset + 8 <= end {
+        read_at(file, offset, &mut header[..8])?;
+        let mut size = u32::from_be_bytes(header[0..4].try_into().unwrap()) as u64;
+        let mut header_size = 8;
+        if size == 1 {
+            read_at(file, offset, &mut header)?;
+            size = u64::from_be_bytes(header[8..16].try_into().unwrap());
+            header_size = 16;
+        } else if size == 0 {
+            size = end - offset; // Box extends to the end of the file
+        }
+        if size < header_size {
+            return Err(Error::new(ErrorKind::InvalidData, "Invalid MP4 box size"));
+        }
+        if &header[4..8] == b"mdat" {
+            ranges.push(offset + header_size..(offset + size).min(end));
+        }
+        offset += size;
+    }
+    Ok(ranges)
+}
+
+/// RIFF and AIFF: the content of the sample data chunk
+fn chunk_payload(
+    file: &mut File,
+    start: u64,
+    end: u64,
+    form: &[u8; 4],
+    data_id: &[u8; 4],
+    big_endian: bool,
+) -> Result<Vec<Range<u64>>, Error> {
+    let mut id = [0u8; 4];
+    read_at(file, start + 8, &mut id)?;
+    if &id != form && !(big_endian && &id == b"AIFC") {
+        return Ok(vec![Range { start, end }]);
+    }
+
+    let mut ranges = Vec::new();
+    let mut offset = start + 12;
+    let mut header = [0u8; 8];
+    while offset + 8 <= end {
+        read_at(file, offset, &mut header)?;
+        let bytes: [u8; 4] = header[4..8].try_into().unwrap();
+        let size = if big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) } as u64;
+        if &header[0..4] == data_id {
+            ranges.push(offset + 8..(offset + 8 + size).min(end));
+        }
+        offset += 8 + size + (size & 1); // Chunks are padded to an even size
+    }
+    Ok(ranges)
+}
+
+/// Join ranges which follow each other directly
+fn merge_ranges(ranges: Vec<Range<u64>>) -> Vec<Range<u64>> {
+    let mut merged: Vec<Range<u64>> = Vec::new();
+    for range in ranges.into_iter().filter(|r| r.start < r.end) {
+        match merged.last_mut() {
+            Some(last) if last.end == range.start => last.end = range.end,
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload(name: &str, data: &[u8]) -> Vec<Range<u64>> {
+        let path = std::env::temp_dir().join(format!("payload_utils_{}", name));
+        std::fs::write(&path, data).unwrap();
+        let ranges = get_audio_payload(&path);
+        std::fs::remove_file(&path).unwrap();
+        ranges.unwrap()
+    }
+
+    fn id3v1() -> Vec<u8> {
+        let mut tag = b"TAG".to_vec();
+        tag.resize(ID3V1_SIZE as usize, b' ');
+        tag
+    }
+
+    #[test]
+    fn merge_ranges_joins_adjacent_and_drops_empty() {
+        assert_eq!(merge_ranges(vec![0..4, 4..8, 8..8, 10..12, 12..13]), vec![0..8, 10..13]);
+        assert!(merge_ranges(vec![Range { start: 5, end: 5 }]).is_empty());
+    }
+
+    #[test]
+    fn tiny_and_tag_only_files() {
+        assert_eq!(payload("tiny", b"abcde"), vec![0..5]);
+        assert!(payload("tag_only", &id3v1()).is_empty());
+
+        let mut data = b"abcde".to_vec();
+        data.extend(id3v1());
+        assert_eq!(payload("tiny_tagged", &data), vec![0..5]);
+    }
+
+    #[test]
+    fn id3_tags_are_skipped() {
+        let mut data = b"ID3\x03\0\0\0\0\0\x05".to_vec();
+        data.extend_from_slice(&[0; 5]);
+        data.extend_from_slice(&[0xaa; 100]);
+        data.extend(id3v1());
+        assert_eq!(payload("id3", &data), vec![15..115]);
+    }
+
+    #[test]
+    fn flac_metadata_blocks_are_skipped() {
+        let mut data = b"fLaC".to_vec();
+        data.extend_from_slice(&[0x00, 0, 0, 2, 1, 2]); // STREAMINFO
+        data.extend_from_slice(&[0x84, 0, 0, 3, 1, 2, 3]); // Last block, VORBIS_COMMENT
+        data.extend_from_slice(&[0xff; 20]);
+        assert_eq!(payload("flac", &data), vec![17..37]);
+    }
+
+    #[test]
+    fn wav_data_chunk_only() {
+        let mut data = b"RIFF\0\0\0\0WAVE".to_vec();
+        data.extend_from_slice(b"fmt \x02\0\0\0\x01\x02");
+        data.extend_from_slice(b"data\x04\0\0\0\x01\x02\x03\x04");
+        data.extend_from_slice(b"LIST\x02\0\0\0ab");
+        assert_eq!(payload("wav", &data), vec![30..34]);
+    }
+}
@@ -1,3 +1,6 @@
+use regex::Regex;
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
 const CHARS_TO_REMOVE: [char; 27] = ['\'', '\"', '/', '=', '-', ',', '.', ':', ';', '\n', '\t', '<', '>', '^', '`', '&', '%', '$', '£', '@', '#', '!', '?', '§', '°', '*', '+'];
 
 // Remove multiples of the given character i.e. '  ' -> ' ', Trims char at end
@@ -56,3 +59,162 @@ pub fn normalize_option(value: Option<&String>) -> String {
         normalize_string(value.unwrap())
     }
 }
+
+/// Configurable normalization of titles and names, edited in the Settings tab.
+///
+/// The steps are applied in this order: NFKC, lowercase, diacritic folding, 'feat.' removal,
+/// bracket stripping, the user regex rules and finally the removal of all punctuation and
+/// multiple spaces.
+#[derive(Debug, Clone)]
+pub struct NormalizeRules {
+    pub nfkc: bool,            // Unicode compatibility composition, i.e. full width -> ascii
+    pub fold_diacritics: bool, // 'é' -> 'e'
+    pub remove_feat: bool,     // Remove 'feat. Artist' parts
+    pub strip_brackets: bool,  // Remove parts in (), [] and {}
+    pub keep_words: String,    // Comma separated. Bracketed parts containing one of them are kept
+    pub regex_rules: String,   // One rule per line in the form 'pattern => replacement'
+    regexes: Vec<(Regex, String)>,
+    compiled_rules: Option<String>, // The rules the regexes were compiled from
+    feat: Option<Regex>,
+}
+
+impl NormalizeRules {
+    pub fn new() -> Self {
+        Self {
+            nfkc: true,
+            fold_diacritics: true,
+            remove_feat: true,
+            strip_brackets: true,
+            keep_words: "live, remix, mix, acoustic, unplugged, demo, instrumental, edit, remaster, remastered, version"
+                .to_string(),
+            regex_rules: String::new(),
+            regexes: Vec::new(),
+            compiled_rules: None,
+            feat: None,
+        }
+    }
+
+    /// Compile the regex rules. Must be called after 'regex_rules' was changed, it does nothing
+    /// if the rules are unchanged.
+    pub fn compile(&mut self) -> Result<(), regex::Error> {
+        if self.feat.is_none() {
+            // A bracketed part up to its closing bracket, or a bare 'feat.' with an artist up
+            // to the next bracket, so a following '(Live)' is still stripped or kept
+            self.feat = Some(Regex::new(
+                r"[(\[{]\s*(feat\.?|ft\.|featuring)\s[^)\]}]*[)\]}]|\b(feat\.|ft\.|featuring)\s+[^\s(\[{)\]}][^(\[{)\]}]*",
+            )?);
+        }
+        if self.compiled_rules.as_ref() == Some(&self.regex_rules) {
+            return Ok(());
+        }
+
+        self.regexes.clear();
+        self.compiled_rules = None;
+        for line in self.regex_rules.lines().map(|l| l.trim()).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+            let (pattern, replacement) = line.split_once("=>").unwrap_or((line, ""));
+            self.regexes.push((Regex::new(pattern.trim())?, replacement.trim().to_string()));
+        }
+        self.compiled_rules = Some(self.regex_rules.clone());
+        Ok(())
+    }
+
+    /// Normalize a value. The 'feat.' removal and the regex rules need a 'compile' first
+    pub fn normalize(&self, value: &str) -> String {
+        let mut s = if self.nfkc { value.nfkc().collect::<String>() } else { value.to_string() };
+        s = s.to_lowercase();
+        if self.fold_diacritics {
+            s = s.nfd().filter(|c| !is_combining_mark(*c)).collect();
+        }
+        if self.remove_feat {
+            if let Some(feat) = &self.feat {
+                s = feat.replace_all(&s, "").to_string();
+            }
+        }
+        if self.strip_brackets {
+            s = self.strip_bracketed(&s);
+        }
+        for (regex, replacement) in self.regexes.iter() {
+            s = regex.replace_all(&s, replacement.as_str()).to_string();
+        }
+
+        let s: String = s.chars().filter(|c| c.is_alphanumeric() || c.is_whitespace()).collect();
+        s.split_whitespace().collect::<Vec<&str>>().join(" ")
+    }
+
+    /// Remove the bracketed parts which contain none of the keep words. Kept parts lose their
+    /// brackets, so 'Song (Live)' and 'Song - Live' are equal.
+    fn strip_bracketed(&self, value: &str) -> String {
+        let keep_words: Vec<String> = self.keep_words.split(',').map(|w| w.trim().to_lowercase()).filter(|w| !w.is_empty()).collect();
+
+        let mut s = String::with_capacity(value.len());
+        let mut part = String::new();
+        let mut depth = 0;
+        for c in value.chars() {
+            match c {
+                '(' | '[' | '{' => {
+                    depth += 1;
+                    part.push(' ');
+                }
+                ')' | ']' | '}' if depth > 0 => {
+                    depth -= 1;
+                    if depth == 0 {
+                        if part.split(|c: char| !c.is_alphanumeric()).any(|word| keep_words.iter().any(|k| k == word)) {
+                            s.push(' ');
+                            s.push_str(&part);
+                        }
+                        part.clear();
+                    }
+                }
+                _ if depth > 0 => part.push(c),
+                _ => s.push(c),
+            }
+        }
+        // An unclosed bracket is not a bracketed part, keep the text after it
+        s.push_str(&part);
+        s
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalize(value: &str) -> String {
+        let mut rules = NormalizeRules::new();
+        rules.compile().unwrap();
+        rules.normalize(value)
+    }
+
+    #[test]
+    fn bare_feat_stops_at_the_next_bracket() {
+        assert_eq!(normalize("Song feat. X (Live)"), "song live");
+        assert_eq!(normalize("Song feat. X (Remix)"), "song remix");
+        assert_eq!(normalize("Song ft. X [Bonus]"), "song");
+    }
+
+    #[test]
+    fn bracketed_feat_is_removed_up_to_its_bracket() {
+        assert_eq!(normalize("Song (feat. X)"), "song");
+        assert_eq!(normalize("Song [featuring X & Y] (Acoustic)"), "song acoustic");
+        assert_eq!(normalize("Song {feat X}"), "song");
+    }
+
+    #[test]
+    fn feat_as_a_word_is_kept() {
+        assert_eq!(normalize("A Feat To Remember"), "a feat to remember");
+        assert_eq!(normalize("Feat."), "feat");
+    }
+
+    #[test]
+    fn brackets_without_keep_words_are_stripped() {
+        assert_eq!(normalize("Song (2011 Remaster)"), "song 2011 remaster");
+        assert_eq!(normalize("Song (Bonus Track)"), "song");
+        assert_eq!(normalize("Song (Live"), "song live");
+    }
+
+    #[test]
+    fn diacritics_and_full_width_are_folded() {
+        assert_eq!(normalize("Café"), "cafe");
+        assert_eq!(normalize("ＳＯＮＧ"), "song");
+    }
+}
@@ -0,0 +1,158 @@
+use lofty::tag::ItemKey;
+use std::collections::HashMap;
+
+/// Prefix of keys which have no canonical name
+pub const CUSTOM_PREFIX: &str = "Custom:";
+
+/// Prefixes of user defined fields, stripped before the name is looked up
+const FIELD_PREFIXES: [&str; 4] = ["TXXX:", "----:com.apple.iTunes:", "----:", "WXXX:"];
+
+/// Raw field names of ID3v2 frames, Vorbis comments, APE items, MP4 atoms and RIFF INFO chunks
+/// which lofty does not map, with their canonical name. Names are compared uppercase without
+/// spaces, '_' and '-'.
+const ALIASES: [(&str, &str); 62] = [
+    // Vorbis comments, APE items and TXXX/freeform descriptions as written by common taggers
+    ("ALBUMARTIST", "AlbumArtist"),
+    ("ARTISTS", "TrackArtists"),
+    ("ORIGINALYEAR", "OriginalReleaseDate"),
+    ("ORIGINALDATE", "OriginalReleaseDate"),
+    ("ORIGINALRELEASEDATE", "OriginalReleaseDate"),
+    ("TOTALTRACKS", "TrackTotal"),
+    ("TRACKTOTAL", "TrackTotal"),
+    ("TOTALDISCS", "DiscTotal"),
+    ("DISCTOTAL", "DiscTotal"),
+    ("DISCSUBTITLE", "SetSubtitle"),
+    ("COMPILATION", "FlagCompilation"),
+    ("ITUNESCOMPILATION", "FlagCompilation"),
+    ("BARCODE", "Barcode"),
+    ("UPC", "Barcode"),
+    ("CATALOGNUMBER", "CatalogNumber"),
+    ("LABEL", "Label"),
+    ("ORGANIZATION", "Label"),
+    ("PUBLISHER", "Publisher"),
+    ("MEDIA", "OriginalMediaType"),
+    ("ISRC", "Isrc"),
+    ("ASIN", "Asin"),
+    ("SCRIPT", "Script"),
+    ("LANGUAGE", "Language"),
+    ("ENCODEDBY", "EncodedBy"),
+    ("ENCODER", "EncoderSoftware"),
+    ("ENCODERSETTINGS", "EncoderSettings"),
+    ("BPM", "Bpm"),
+    ("MOOD", "Mood"),
+    ("WORK", "Work"),
+    ("MOVEMENTNAME", "Movement"),
+    ("COMMENT", "Comment"),
+    ("DESCRIPTION", "Description"),
+    ("LYRICS", "Lyrics"),
+    ("UNSYNCEDLYRICS", "Lyrics"),
+    ("RELEASETYPE", "ReleaseType"),
+    ("MUSICBRAINZALBUMTYPE", "ReleaseType"),
+    ("RELEASESTATUS", "ReleaseStatus"),
+    ("MUSICBRAINZALBUMSTATUS", "ReleaseStatus"),
+    ("RELEASECOUNTRY", "ReleaseCountry"),
+    ("MUSICBRAINZALBUMRELEASECOUNTRY", "ReleaseCountry"),
+    ("ACOUSTIDID", "AcoustId"),
+    ("ACOUSTIDFINGERPRINT", "AcoustIdFingerprint"),
+    ("MUSICBRAINZTRACKID", "MusicBrainzRecordingId"), // Picard writes the recording ID as 'track id'
+    ("MUSICBRAINZRECORDINGID", "MusicBrainzRecordingId"),
+    ("MUSICBRAINZRELEASETRACKID", "MusicBrainzTrackId"),
+    ("MUSICBRAINZALBUMID", "MusicBrainzReleaseId"),
+    ("MUSICBRAINZRELEASEGROUPID", "MusicBrainzReleaseGroupId"),
+    ("MUSICBRAINZARTISTID", "MusicBrainzArtistId"),
+    ("MUSICBRAINZALBUMARTISTID", "MusicBrainzReleaseArtistId"),
+    ("MUSICBRAINZWORKID", "MusicBrainzWorkId"),
+    ("MUSICBRAINZDISCID", "MusicBrainzDiscId"),
+    ("REPLAYGAINTRACKGAIN", "ReplayGainTrackGain"),
+    ("REPLAYGAINTRACKPEAK", "ReplayGainTrackPeak"),
+    ("REPLAYGAINALBUMGAIN", "ReplayGainAlbumGain"),
+    ("REPLAYGAINALBUMPEAK", "ReplayGainAlbumPeak"),
+    // ID3v2 frames without a lofty mapping
+    ("TSO2", "AlbumArtistSortOrder"),
+    ("TSOC", "ComposerSortOrder"),
+    ("TCMP", "FlagCompilation"),
+    ("TDOR", "OriginalReleaseDate"),
+    // RIFF INFO chunks
+    ("ICRD", "RecordingDate"),
+    ("ITRK", "TrackNumber"),
+    ("IGNR", "Genre"),
+];
+
+/// Text fields which can be written, found by their canonical name
+const ITEM_KEYS: [ItemKey; 70] = [
+    ItemKey::AlbumTitle, ItemKey::SetSubtitle, ItemKey::TrackTitle, ItemKey::TrackSubtitle,
+    ItemKey::OriginalAlbumTitle, ItemKey::OriginalArtist, ItemKey::OriginalLyricist,
+    ItemKey::AlbumTitleSortOrder, ItemKey::AlbumArtistSortOrder, ItemKey::TrackTitleSortOrder,
+    ItemKey::TrackArtistSortOrder, ItemKey::ComposerSortOrder, ItemKey::AlbumArtist,
+    ItemKey::TrackArtist, ItemKey::Arranger, ItemKey::Writer, ItemKey::Composer, ItemKey::Conductor,
+    ItemKey::Director, ItemKey::Engineer, ItemKey::Lyricist, ItemKey::MixDj, ItemKey::MixEngineer,
+    ItemKey::Performer, ItemKey::Producer, ItemKey::Publisher, ItemKey::Label, ItemKey::Remixer,
+    ItemKey::DiscNumber, ItemKey::DiscTotal, ItemKey::TrackNumber, ItemKey::TrackTotal,
+    ItemKey::RecordingDate, ItemKey::Year, ItemKey::ReleaseDate, ItemKey::OriginalReleaseDate,
+    ItemKey::Isrc, ItemKey::Barcode, ItemKey::CatalogNumber, ItemKey::Work, ItemKey::Movement,
+    ItemKey::MovementNumber, ItemKey::MovementTotal, ItemKey::MusicBrainzRecordingId,
+    ItemKey::MusicBrainzTrackId, ItemKey::MusicBrainzReleaseId, ItemKey::MusicBrainzReleaseGroupId,
+    ItemKey::MusicBrainzArtistId, ItemKey::MusicBrainzReleaseArtistId, ItemKey::MusicBrainzWorkId,
+    ItemKey::FlagCompilation, ItemKey::OriginalMediaType, ItemKey::EncodedBy,
+    ItemKey::EncoderSoftware, ItemKey::EncoderSettings, ItemKey::ReplayGainAlbumGain,
+    ItemKey::ReplayGainAlbumPeak, ItemKey::ReplayGainTrackGain, ItemKey::ReplayGainTrackPeak,
+    ItemKey::Genre, ItemKey::InitialKey, ItemKey::Mood, ItemKey::Bpm, ItemKey::CopyrightMessage,
+    ItemKey::License, ItemKey::Comment, ItemKey::Description, ItemKey::Language, ItemKey::Script,
+    ItemKey::Lyrics,
+];
+
+/// Text fields which can hold several values, i.e. one item per artist
+const MULTI_VALUED_KEYS: [ItemKey; 14] = [
+    ItemKey::TrackArtist, ItemKey::AlbumArtist, ItemKey::Composer, ItemKey::Lyricist,
+    ItemKey::Performer, ItemKey::Producer, ItemKey::Arranger, ItemKey::Writer, ItemKey::Conductor,
+    ItemKey::Remixer, ItemKey::Genre, ItemKey::Mood, ItemKey::Label, ItemKey::Publisher,
+];
+
+/// Returns the canonical field name of a tag item key.
+///
+/// Keys known to lofty use their ItemKey name, which is the same for all tag formats. Raw
+/// keys of other fields are looked up in ALIASES. Fields without a canonical name get the
+/// CUSTOM_PREFIX, i.e. 'TXXX:My Field' -> 'Custom:My Field'.
+pub fn canonical_key(key: &ItemKey) -> String {
+    match key {
+        ItemKey::Unknown(raw) => canonical_name(raw),
+        known => format!("{:?}", known),
+    }
+}
+
+/// Returns the canonical name of a raw field name
+pub fn canonical_name(raw: &str) -> String {
+    let name = FIELD_PREFIXES.iter().find_map(|prefix| raw.strip_prefix(prefix)).unwrap_or(raw).trim();
+    let lookup: String = name.chars().filter(|c| !matches!(c, ' ' | '_' | '-')).collect::<String>().to_uppercase();
+    match ALIASES.iter().find(|(alias, _)| *alias == lookup) {
+        Some((_, canonical)) => canonical.to_string(),
+        None => format!("{}{}", CUSTOM_PREFIX, name),
+    }
+}
+
+/// Returns true if the key has no canonical name
+pub fn is_custom(key: &str) -> bool {
+    key.starts_with(CUSTOM_PREFIX)
+}
+
+/// Returns the item key of a canonical name, or None if the field can't be written
+pub fn item_key(name: &str) -> Option<ItemKey> {
+    ITEM_KEYS.iter().find(|key| format!("{:?}", key) == name).cloned()
+}
+
+/// Returns true if the field can hold several values
+pub fn is_multi_valued(key: &ItemKey) -> bool {
+    MULTI_VALUED_KEYS.contains(key)
+}
+
+/// Returns the trimmed value of a tag, or None if it is missing or empty
+pub fn tag_value<'a>(tags: &'a HashMap<String, String>, key: &str) -> Option<&'a str> {
+    tags.get(key).map(|v| v.trim()).filter(|v| !v.is_empty())
+}
+
+/// Returns the number and total of a track or disc number, i.e. '3' or '3/12'
+pub fn tag_number(value: &str) -> Option<(u32, Option<u32>)> {
+    let mut parts = value.split('/');
+    let number = parts.next()?.trim().parse().ok()?;
+    Some((number, parts.next().and_then(|total| total.trim().parse().ok())))
+}
@@ -1,10 +1,14 @@
 use crate::scanner::mediatype::{MediaGroup, ScanType};
-use crate::scanner::messenger::Messenger;
+use crate::scanner::messenger::{Finding, Messenger};
+use crate::scanner::options::ScanOptions;
+use crate::scanner::profile::MatchProfile;
 use crate::scanner::scanner::scan;
 
 use std::{
+    collections::BTreeSet,
+    fs::File,
+    io::Write,
     path::Path,
-    sync::MutexGuard,
     thread::{self},
     time::Duration,
     vec::Vec,
@@ -19,15 +23,20 @@ use egui_file_dialog::{FileDialog};
 
 use crate::components::notifications::NotificationBar;
 use crate::components::{duplicates_table};
+use crate::components::tag_editor::TagEditor;
+use crate::components::tag_merge::TagMerge;
+use crate::components::waveform::WaveformCache;
 use egui_comps::tabbar::TabBar;
 use crate::app::ApplicationState;
 
 const BUTTON_HEIGHT: f32 = 32.0;
+const REPORT_NAME: &str = "./report.txt";
 
-const TAB_COLORS: [&[Color32]; 3] = [
+const TAB_COLORS: [&[Color32]; 4] = [
     &[Color32::DARK_BLUE, Color32::LIGHT_BLUE],
     &[Color32::DARK_RED, Color32::LIGHT_RED],
     &[Color32::DARK_GREEN, Color32::LIGHT_GREEN],
+    &[Color32::BROWN, Color32::KHAKI],
 ];
 
 #[derive(PartialEq, Copy, Clone)]
@@ -35,13 +44,15 @@ enum ShowTab {
     Scanned,
     Errors,
     Duplicates,
+    Reports,
 }
 impl ShowTab {
     pub fn from(tab_idx: usize) -> Self {
         match tab_idx {
             0 => ShowTab::Scanned,
             1 => ShowTab::Errors,
-            _ => ShowTab::Duplicates,
+            2 => ShowTab::Duplicates,
+            _ => ShowTab::Reports,
         }
     }
 
@@ -55,8 +66,16 @@ pub struct DuplicateScannerUI {
     scan_type: ScanType,
     selected_tab: usize,
     path: String,
+    reference: String,
     file_dialog: FileDialog,
+    select_reference: bool, // FileDialog was opened for the reference path
+    match_profile: String,  // Name of the profile used by METADATA scans
     messenger: Messenger,
+    tag_editor: TagEditor,
+    tag_merge: TagMerge,
+    waveforms: WaveformCache,
+    report_category: String, // Category shown in the Reports tab. Empty = all
+    report_filter: String,   // Text the shown findings must contain
     scanning: bool,
     handle: Option<std::thread::JoinHandle<()>>,
 }
@@ -67,8 +86,16 @@ impl DuplicateScannerUI {
             scan_type: ScanType::METADATA,
             selected_tab: 0, // select first tab as default
             path: String::new(),
+            reference: String::new(),
             file_dialog: FileDialog::new(),
+            select_reference: false,
+            match_profile: MatchProfile::full().name,
             messenger: Messenger::new(),
+            tag_editor: TagEditor::new(),
+            tag_merge: TagMerge::new(),
+            waveforms: WaveformCache::new(),
+            report_category: String::new(),
+            report_filter: String::new(),
             scanning: false,
             handle: None,
         }
@@ -76,6 +103,7 @@ impl DuplicateScannerUI {
 
     fn clear(&mut self) {
         self.messenger.clear();
+        self.waveforms.clear();
         self.scanning = false;
     }
 
@@ -87,11 +115,7 @@ impl DuplicateScannerUI {
     }
 
     fn have_results(&self) -> bool {
-        self.messenger.cntres() > 0 || self.messenger.cnterr() > 0 || self.messenger.cntstd() > 0
-    }
-
-    fn get_checked(&self) -> MutexGuard<Vec<bool>> {
-        self.messenger.checked()
+        self.messenger.cntres() > 0 || self.messenger.cnterr() > 0 || self.messenger.cntstd() > 0 || self.messenger.cntrep() > 0
     }
 
     fn get_tab_color(&self, ui: &Ui) -> Color32 {
@@ -111,6 +135,8 @@ pub fn duplicate_ui(
     state: &mut ApplicationState,
     dss: &mut DuplicateScannerUI,
     media_groups: Vec<MediaGroup>,
+    mut scan_options: ScanOptions,
+    match_profiles: &[MatchProfile],
     notification_bar: &mut NotificationBar,
 ) {
     let is_scanning = dss.is_scanning();
@@ -135,6 +161,22 @@ pub fn duplicate_ui(
                 // Open Directory Symbol
                 if ui.add_enabled(!is_scanning, egui::Button::new(" \u{e613} "))
                     .clicked() {
+                    dss.select_reference = false;
+                    dss.file_dialog.select_directory();
+                }
+            });
+            ui.end_row();
+
+            // Optional reference directory (curated archive)
+            ui.strong("Reference:");
+            ui.horizontal(|ui| {
+                ui.add(TextEdit::singleline(&mut dss.reference)
+                    .hint_text("Only report files which already exist in this directory")
+                    .desired_width(ui.available_width() - 50.0));
+
+                if ui.add_enabled(!is_scanning, egui::Button::new(" \u{e613} "))
+                    .clicked() {
+                    dss.select_reference = true;
                     dss.file_dialog.select_directory();
                 }
             });
@@ -148,8 +190,25 @@ pub fn duplicate_ui(
                     .show_ui(ui, |ui| {
                         ui.selectable_value(&mut dss.scan_type, ScanType::BINARY, "Binary");
                         ui.selectable_value(&mut dss.scan_type, ScanType::METADATA, "Metadata");
+                        ui.selectable_value(&mut dss.scan_type, ScanType::DIRECTORY, "Directory");
+                        ui.selectable_value(&mut dss.scan_type, ScanType::IMAGE_SIMILAR, "Similar images");
+                        ui.selectable_value(&mut dss.scan_type, ScanType::ACOUSTIC, "Acoustic");
+                        ui.selectable_value(&mut dss.scan_type, ScanType::AUDIO_PAYLOAD, "Audio payload");
+                        ui.selectable_value(&mut dss.scan_type, ScanType::LINT, "Tag lint");
                     });
 
+                // Match profile for METADATA. LINT reports files without a key of the profile
+                if dss.scan_type == ScanType::METADATA || dss.scan_type == ScanType::LINT {
+                    ui.label("Profile:");
+                    egui::ComboBox::from_id_source("match_profile")
+                        .selected_text(&dss.match_profile)
+                        .show_ui(ui, |ui| {
+                            for profile in match_profiles.iter() {
+                                ui.selectable_value(&mut dss.match_profile, profile.name.clone(), &profile.name);
+                            }
+                        });
+                }
+
                 // Scan / Abort Buttons
                 ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
                     // Abort
@@ -188,9 +247,17 @@ pub fn duplicate_ui(
 
                         let messenger = dss.messenger.clone();
                         let path = dss.path.clone();
+                        let reference = dss.reference.clone();
                         let scan_type = dss.scan_type;
+                        if let Some(profile) = match_profiles.iter().find(|p| p.name == dss.match_profile) {
+                            scan_options.match_profile = profile.clone();
+                        }
+                        let mut editor_options = scan_options.clone();
+                        let metadata = scan_type == ScanType::METADATA && editor_options.prepare().is_ok();
+                        dss.tag_editor.options = metadata.then_some(editor_options);
                         dss.handle = Some(thread::spawn(move || {
-                            scan(Path::new(&path), scan_type, media_groups, messenger);
+                            let reference = if reference.is_empty() { None } else { Some(Path::new(&reference)) };
+                            scan(Path::new(&path), reference, scan_type, media_groups, scan_options, messenger);
                         }));
                     } // clicked
                 }) // with_layout;
@@ -205,6 +272,7 @@ pub fn duplicate_ui(
         format!("Scanned [{}]", dss.messenger.cntstd()),
         format!("Problems [{}]", dss.messenger.cnterr()),
         format!("Duplicates [{}]", dss.messenger.cntres()),
+        format!("Reports [{}]", dss.messenger.cntrep()),
     };
 
     ui.add_enabled(have_results, TabBar::new(cols, &mut dss.selected_tab, &ui.visuals())
@@ -219,8 +287,9 @@ pub fn duplicate_ui(
     // Open FileDialog
     match dss.file_dialog.update(ctx).selected() {
         Some(path) => {
-            dss.path.clear();
-            dss.path.push_str(path.to_str().unwrap());
+            let target = if dss.select_reference { &mut dss.reference } else { &mut dss.path };
+            target.clear();
+            target.push_str(path.to_str().unwrap());
         }
         _ => ()
     }
@@ -235,8 +304,10 @@ pub fn duplicate_ui(
 
     if ShowTab::from(dss.selected_tab) == ShowTab::Duplicates {
         let mut stack = dss.messenger.reslog();
-        let mut checked = dss.get_checked();
-        duplicates_table::mediatable(ui, state, &mut stack, &mut checked);
+        let mut checked = dss.messenger.checked();
+        duplicates_table::mediatable(ui, state, &mut stack, &mut checked, &mut dss.tag_editor, &mut dss.tag_merge, &dss.waveforms);
+        dss.tag_editor.show(ctx, &mut stack, &mut checked);
+        dss.tag_merge.show(ctx, &mut stack, &mut checked);
     } else {
         let color = dss.get_tab_color(&ui);
 
@@ -258,6 +329,46 @@ pub fn duplicate_ui(
                     ui.add(Label::new(rt).wrap_mode(TextWrapMode::Extend));
                 }
             });
+        } else if ShowTab::from(dss.selected_tab) == ShowTab::Reports {
+            let stack = dss.messenger.reports();
+            let categories: BTreeSet<&str> = stack.iter().map(|f| f.category.as_str()).collect();
+            let filter = dss.report_filter.to_lowercase();
+            let findings: Vec<&Finding> = stack
+                .iter()
+                .filter(|f| dss.report_category.is_empty() || f.category == dss.report_category)
+                .filter(|f| filter.is_empty() || f.message.to_lowercase().contains(&filter) || f.path.to_lowercase().contains(&filter))
+                .collect();
+
+            ui.horizontal(|ui| {
+                ui.label("Category:");
+                let selected = if dss.report_category.is_empty() { "All" } else { dss.report_category.as_str() };
+                egui::ComboBox::from_id_source("report_category")
+                    .selected_text(selected.to_string())
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut dss.report_category, String::new(), "All");
+                        for category in categories.iter() {
+                            ui.selectable_value(&mut dss.report_category, category.to_string(), *category);
+                        }
+                    });
+                ui.label("Filter:");
+                ui.add(TextEdit::singleline(&mut dss.report_filter).desired_width(200.0));
+                ui.label(format!("{} of {}", findings.len(), stack.len()));
+                if ui.add_enabled(!findings.is_empty(), Button::new("Export")).clicked() {
+                    match export_report(&findings) {
+                        Ok(count) => dss.messenger.set_info(format!("{} findings written to file {}", count, REPORT_NAME)),
+                        Err(e) => dss.messenger.push_errlog(format!("Could not write file {} due to error {}", REPORT_NAME, e)),
+                    }
+                }
+            });
+
+            scroll_area.show_rows(ui, row_height, findings.len(), |ui, row_range| {
+                for row in row_range {
+                    let finding = findings[row];
+                    let msg = format!("{:<12} {} : {}", finding.category, finding.message, finding.path);
+                    let rt = RichText::new(msg).color(color);
+                    ui.add(Label::new(rt).wrap_mode(TextWrapMode::Extend));
+                }
+            });
         }
         if is_scanning && !ctx.has_requested_repaint() {
             ctx.request_repaint();
@@ -268,3 +379,12 @@ pub fn duplicate_ui(
         ctx.request_repaint_after(Duration::from_millis(250));
     }
 }
+
+/// Write the findings as tab separated lines: category, message and path
+fn export_report(findings: &[&Finding]) -> Result<usize, std::io::Error> {
+    let mut f = File::create(REPORT_NAME)?;
+    for finding in findings.iter() {
+        writeln!(f, "{}\t{}\t{}", finding.category, finding.message, finding.path)?;
+    }
+    Ok(findings.len())
+}
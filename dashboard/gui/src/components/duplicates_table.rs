@@ -6,7 +6,14 @@ use egui_modal::*;
 use std::path::Path;
 use std::collections::HashMap;
 use crate::app::ApplicationState;
-use crate::components::basic::lofty_utils::filter_tags;
+use crate::components::basic::image_utils::get_thumbnail;
+use crate::components::tag_editor::TagEditor;
+use crate::components::tag_merge::TagMerge;
+use crate::components::waveform::{waveforms_ui, WaveformCache};
+use crate::components::basic::lofty_utils::{filter_tags, get_cover};
+
+const THUMBNAIL_SIZE: u32 = 160;
+const WAVEFORM_WIDTH: f32 = 400.0;
 
 const CHARS_PER_LINE: [(f32, f32, f32); 9] = [
     (0.7, 1216.0, 130.0),
@@ -44,7 +51,7 @@ fn get_checked_idxs(checked: &mut MutexGuard<Vec<bool>>) -> Vec<usize> {
     checked_idxs
 }
 
-fn delete_checked_duplicates(duplicates: &mut MutexGuard<Vec<HashMap<String, String>>>, checked: &mut MutexGuard<Vec<bool>>) {
+pub fn delete_checked_duplicates(duplicates: &mut MutexGuard<Vec<HashMap<String, String>>>, checked: &mut MutexGuard<Vec<bool>>) {
     // Get the index-positions
     let checked_idxs = get_checked_idxs(checked);
 
@@ -62,14 +69,76 @@ fn delete_checked_duplicates(duplicates: &mut MutexGuard<Vec<HashMap<String, Str
     }
 }
 
+/// Select all members of each group except the best one by 'QUALITY_RANK'. Ties keep the first
+/// member. In reference mode the members which are not better than the reference copy are
+/// selected. Intact copies are preferred to copies with an 'INTEGRITY' problem. With
+/// 'prefer_artwork' the size of the cover is compared before the quality.
+fn auto_mark(duplicates: &MutexGuard<Vec<HashMap<String, String>>>, checked: &mut MutexGuard<Vec<bool>>, prefer_artwork: bool) {
+    let mut groups: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (idx, map) in duplicates.iter().enumerate() {
+        if let Some(group) = map.get("GROUP") {
+            groups.entry(group.as_str()).or_default().push(idx);
+        }
+    }
+
+    let pixels = |map: &HashMap<String, String>, key: &str| -> u64 {
+        if prefer_artwork { map.get(key).and_then(|p| p.parse().ok()).unwrap_or(0) } else { 0 }
+    };
+    // Files which were not checked count as intact
+    let intact = |map: &HashMap<String, String>, key: &str| map.get(key).map_or(true, |i| i == "OK");
+    let rank = |idx: usize| {
+        let map = &duplicates[idx];
+        (intact(map, "INTEGRITY"), pixels(map, "CoverPixels"), map.get("QUALITY_RANK").map_or("", |r| r.as_str()))
+    };
+    for members in groups.values() {
+        let first = &duplicates[members[0]];
+        let reference_rank = first
+            .get("REFERENCE_QUALITY_RANK")
+            .map(|rank| (intact(first, "REFERENCE_INTEGRITY"), pixels(first, "REFERENCE_COVER_PIXELS"), rank.as_str()));
+        let best = members.iter().copied().rev().max_by_key(|idx| rank(*idx));
+        for idx in members.iter().copied() {
+            checked[idx] = match reference_rank {
+                Some(reference_rank) => rank(idx) <= reference_rank,
+                None => Some(idx) != best,
+            };
+        }
+    }
+}
+
+/// Show the cover of the file as thumbnail. The texture is loaded once and kept in the egui memory
+fn cover_thumbnail(ui: &mut egui::Ui, path: &str) {
+    let id = Id::new(("cover_thumbnail", path));
+    let texture = match ui.ctx().data(|d| d.get_temp::<Option<TextureHandle>>(id)) {
+        Some(texture) => texture,
+        None => {
+            let texture = get_cover(Path::new(path))
+                .ok()
+                .flatten()
+                .and_then(|data| get_thumbnail(&data, THUMBNAIL_SIZE).ok())
+                .map(|image| {
+                    let size = [image.width() as usize, image.height() as usize];
+                    let image = ColorImage::from_rgba_unmultiplied(size, image.as_raw());
+                    ui.ctx().load_texture(path, image, TextureOptions::LINEAR)
+                });
+            ui.ctx().data_mut(|d| d.insert_temp(id, texture.clone()));
+            texture
+        }
+    };
+    if let Some(texture) = texture {
+        ui.image((texture.id(), texture.size_vec2()));
+    }
+}
+
 pub fn mediatable(ui: &mut egui::Ui,
                   state: &mut ApplicationState,
                   duplicates: &mut MutexGuard<Vec<HashMap<String, String>>>,
                   checked: &mut MutexGuard<Vec<bool>>,
+                  tag_editor: &mut TagEditor,
+                  tag_merge: &mut TagMerge,
+                  waveforms: &WaveformCache,
 )
 {
     // Calculate Sizes
-    let available_width = ui.available_width();
     let available_height = ui.available_height();
     let row_height = egui::TextStyle::Body
         .resolve(ui.style())
@@ -100,6 +169,11 @@ pub fn mediatable(ui: &mut egui::Ui,
         .resizable(false)
         .cell_layout(egui::Layout::left_to_right(egui::Align::Center))
         .column(Column::auto())
+        .column(Column::auto())
+        .column(Column::auto())
+        .column(Column::auto())
+        .column(Column::auto())
+        .column(Column::auto())
         .column(Column::remainder())
         .min_scrolled_height(0.0)
         .max_scroll_height(available_height)
@@ -115,9 +189,10 @@ pub fn mediatable(ui: &mut egui::Ui,
                         modal.title(ui, "Delete selected files?");
                         modal.frame(ui, |ui| {
                             modal.body(ui, format!("Delete the {} selected file(s)?", cnt_checked));
+                            ui.checkbox(&mut tag_merge.enabled, "Merge their tags into the kept copies first");
                         });
                         modal.buttons(ui, |ui| {
-                            if modal.button(ui, "DELETE").clicked() {
+                            if modal.button(ui, "DELETE").clicked() && !(tag_merge.enabled && tag_merge.start(duplicates, checked)) {
                                 delete_checked_duplicates(duplicates, checked);
                             };
                             if modal.button(ui, "CANCEL").clicked() {
@@ -131,6 +206,35 @@ pub fn mediatable(ui: &mut egui::Ui,
                 if ui.add_enabled(cnt_checked > 0, egui::Button::new("\u{e613} Delete")).clicked() {
                     modal.open();
                 }
+                let mut edit = ui.add_enabled(cnt_checked > 0 && tag_editor.is_available(), egui::Button::new("Edit tags"));
+                if !tag_editor.is_available() {
+                    edit = edit.on_disabled_hover_text("Available after a metadata scan");
+                }
+                if edit.clicked() {
+                    tag_editor.open(duplicates, &get_checked_idxs(checked));
+                }
+            });
+            header.col(|ui| {
+                ui.strong("Group");
+            });
+            header.col(|ui| {
+                ui.strong("Matched by");
+            });
+            header.col(|ui| {
+                ui.horizontal(|ui| {
+                    ui.strong("Quality");
+                    if ui.button("Auto-mark").on_hover_text("Select all but the best copy of each group").clicked() {
+                        auto_mark(duplicates, checked, state.prefer_artwork);
+                    }
+                    ui.checkbox(&mut state.prefer_artwork, "Artwork")
+                        .on_hover_text("Prefer the copy with the largest cover");
+                });
+            });
+            header.col(|ui| {
+                ui.strong("Integrity");
+            });
+            header.col(|ui| {
+                ui.strong("Note");
             });
             header.col(|ui| {
                 ui.strong("Path");
@@ -143,7 +247,32 @@ pub fn mediatable(ui: &mut egui::Ui,
                     ui.checkbox(&mut checked[row_index], "");
                 });
                 row.col(|ui| {
-                    let chars_per_line = chars_per_line(state.zoom_factor, available_width);
+                    ui.label(duplicates[row_index].get("GROUP").map_or("", |g| g.as_str()));
+                });
+                row.col(|ui| {
+                    ui.label(duplicates[row_index].get("MATCHED_BY").map_or("", |m| m.as_str()));
+                });
+                row.col(|ui| {
+                    ui.label(duplicates[row_index].get("QUALITY").map_or("", |q| q.as_str()));
+                });
+                row.col(|ui| {
+                    match duplicates[row_index].get("INTEGRITY") {
+                        Some(integrity) if integrity == "OK" => {
+                            ui.label(integrity);
+                        }
+                        Some(integrity) => {
+                            ui.label(RichText::new(integrity).color(state.active_theme.fg_error_text_color_visuals()));
+                        }
+                        None => (),
+                    }
+                });
+                row.col(|ui| {
+                    if let Some(note) = duplicates[row_index].get("NOTE") {
+                        ui.label(RichText::new(note).color(state.active_theme.fg_warn_text_color_visuals()));
+                    }
+                });
+                row.col(|ui| {
+                    let chars_per_line = chars_per_line(state.zoom_factor, ui.available_width());
                     // Show the nn right characters in the table
                     let map = &duplicates[row_index];
                     let s = map.get("PATH").unwrap();
@@ -183,28 +312,52 @@ pub fn mediatable(ui: &mut egui::Ui,
                         .font(FontId::proportional(15.0)));
                     ui.separator();
 
+                    if map.contains_key("Cover") {
+                        cover_thumbnail(ui, path.to_str().unwrap());
+                    }
+
                     ui.horizontal(|ui| {
                         ui.label("Filter: ");
-                        ui.checkbox(&mut state.filter_unknown, "Unknown");
+                        ui.checkbox(&mut state.filter_custom, "Custom");
                         ui.checkbox(&mut state.filter_musicbrainz, "MusicBrainz");
+                        ui.checkbox(&mut state.filter_sources, "Sources");
                     });
 
-                    egui::ScrollArea::vertical()
-                        .auto_shrink(false)
-                        .drag_to_scroll(true)
-                        .show(ui, |ui| {
-                            egui::Grid::new("duplicates_table_grid")
-                                .striped(false)
-                                .num_columns(2)
-                                .show(ui, |ui| {
-                                    let filtered = filter_tags(map, state.filter_unknown, state.filter_musicbrainz);
-                                    for key in filtered {
-                                        ui.label(key);
-                                        ui.label(map.get(key).unwrap());
-                                        ui.end_row();
-                                    }
-                                }); // Grid show
-                        }); // scroll Aerea
+                    // Waveforms of the audio files of the group, next to the tags
+                    let mut members: Vec<(&str, bool)> = Vec::new();
+                    if map.contains_key("Format") {
+                        if let Some(reference) = map.get("REFERENCE") {
+                            members.push((reference.as_str(), false));
+                        }
+                        for (idx, member) in duplicates.iter().enumerate() {
+                            if member.contains_key("Format") && member.get("GROUP").is_some() && member.get("GROUP") == map.get("GROUP") {
+                                members.push((member.get("PATH").unwrap().as_str(), checked[idx]));
+                            }
+                        }
+                    }
+
+                    ui.horizontal_top(|ui| {
+                        if !members.is_empty() {
+                            waveforms_ui(ui, waveforms, &members, WAVEFORM_WIDTH);
+                            ui.separator();
+                        }
+                        egui::ScrollArea::vertical()
+                            .auto_shrink(false)
+                            .drag_to_scroll(true)
+                            .show(ui, |ui| {
+                                egui::Grid::new("duplicates_table_grid")
+                                    .striped(false)
+                                    .num_columns(2)
+                                    .show(ui, |ui| {
+                                        let filtered = filter_tags(map, state.filter_custom, state.filter_musicbrainz, state.filter_sources);
+                                        for key in filtered {
+                                            ui.label(key);
+                                            ui.label(map.get(key).unwrap());
+                                            ui.end_row();
+                                        }
+                                    }); // Grid show
+                            }); // scroll Aerea
+                    });
                 }); // on_hoover_ui
 
                 // Select/Deselect line or use checkbox
@@ -6,3 +6,6 @@ pub mod duplicate;
 pub mod notifications;
 pub mod settings;
 mod duplicates_table;
+mod tag_editor;
+mod tag_merge;
+mod waveform;
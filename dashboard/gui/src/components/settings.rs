@@ -2,6 +2,8 @@
 use crate::{
     app::ApplicationState,
     scanner::mediatype::{MediaGroup, MediaType},
+    scanner::options::ScanOptions,
+    scanner::profile::{load_profiles, save_profiles, MatchField, MatchProfile, Normalization, MATCH_KEYS},
 };
 use eframe::egui::{self};
 use egui_aesthetix::Aesthetix;
@@ -10,12 +12,22 @@ use std::rc::Rc;
 
 pub struct SettingsUI {
     pub media_groups: Vec<MediaGroup>,
+    pub scan_options: ScanOptions,
+    pub match_profiles: Vec<MatchProfile>,
+    edited_profile: usize,  // Index of the profile shown in the editor
+    profile_status: String, // Result of the last save
+    normalize_preview: String, // Sample text for the normalization preview
 }
 
 impl SettingsUI {
     pub fn new() -> Self {
         Self {
             media_groups: MediaType::load_groups(),
+            scan_options: ScanOptions::new(),
+            match_profiles: load_profiles(),
+            edited_profile: 0,
+            profile_status: String::new(),
+            normalize_preview: "Café del Mar (Live) [feat. Someone] {2011 Remaster}".to_string(),
         }
     }
 
@@ -67,6 +79,82 @@ impl SettingsUI {
                     .show(&ctx, |ui| {
                         ctx.settings_ui(ui);
                     });
+                sa.add_space(10.0);
+
+                sa.heading("Scan options");
+                sa.horizontal(|ui| {
+                    ui.vertical(|ui| {
+                        ui.label("Exclude patterns (one glob per line)");
+                        ui.add(egui::TextEdit::multiline(&mut self.scan_options.exclude_patterns)
+                            .hint_text("**/node_modules/**\n*.part")
+                            .desired_rows(4));
+                    });
+                    ui.vertical(|ui| {
+                        ui.label("Include patterns (empty = all files)");
+                        ui.add(egui::TextEdit::multiline(&mut self.scan_options.include_patterns)
+                            .hint_text("Music/**\n*.flac")
+                            .desired_rows(4));
+                    });
+                });
+                sa.horizontal(|ui| {
+                    ui.checkbox(&mut self.scan_options.use_gitignore, "Honour .gitignore");
+                    ui.checkbox(&mut self.scan_options.use_dffignore, "Honour .dffignore");
+                });
+                sa.horizontal(|ui| {
+                    ui.label("Min. size:");
+                    ui.add(egui::DragValue::new(&mut self.scan_options.min_size).speed(1024).suffix(" bytes"));
+                    ui.label("Max. size:");
+                    ui.add(egui::DragValue::new(&mut self.scan_options.max_size).speed(1024).suffix(" bytes"))
+                        .on_hover_text("0 = no limit");
+                });
+                sa.horizontal(|ui| {
+                    ui.checkbox(&mut self.scan_options.follow_links, "Follow symlinks");
+                    ui.checkbox(&mut self.scan_options.skip_hidden, "Skip hidden files");
+                    ui.checkbox(&mut self.scan_options.same_file_system, "Stay on one filesystem");
+                    ui.label("Max. depth:");
+                    ui.add(egui::DragValue::new(&mut self.scan_options.max_depth).range(0..=1000))
+                        .on_hover_text("0 = no limit");
+                });
+                sa.checkbox(&mut self.scan_options.binary_same_type, "Binary: require the same detected file type");
+                sa.horizontal(|ui| {
+                    ui.label("Similar images: max. distance");
+                    ui.add(egui::Slider::new(&mut self.scan_options.image_distance, 0..=20))
+                        .on_hover_text("Number of differing bits of the 64 bit image hash");
+                });
+                sa.horizontal(|ui| {
+                    ui.label("Acoustic: min. similarity");
+                    ui.add(egui::Slider::new(&mut self.scan_options.acoustic_threshold, 30..=100).suffix("%"))
+                        .on_hover_text("Minimal similarity of the audio fingerprints");
+                    ui.label("Fingerprint length:");
+                    ui.add(egui::DragValue::new(&mut self.scan_options.acoustic_seconds).range(10..=600).suffix(" s"));
+                });
+                sa.horizontal(|ui| {
+                    ui.label("Metadata: duration tolerance");
+                    ui.add(egui::DragValue::new(&mut self.scan_options.duration_tolerance).range(0.0..=10.0).speed(0.1).suffix(" s"));
+                    ui.label("Text similarity:");
+                    ui.add(egui::Slider::new(&mut self.scan_options.fuzzy_threshold, 70..=100).suffix("%"))
+                        .on_hover_text("Jaro-Winkler similarity of the text fields. 100% = exact match");
+                });
+                sa.horizontal(|ui| {
+                    ui.checkbox(&mut self.scan_options.musicbrainz_match, "Metadata: match by MusicBrainz recording ID")
+                        .on_hover_text("Files with different IDs never match. Files without the ID are matched by the profile");
+                    ui.add_enabled(
+                        self.scan_options.musicbrainz_match,
+                        egui::Checkbox::new(&mut self.scan_options.musicbrainz_release, "Require the same release and track ID"),
+                    );
+                });
+                sa.checkbox(&mut self.scan_options.cross_album, "Metadata: copies on other albums are duplicates")
+                    .on_hover_text("If on, the album fields of the profile are ignored. If off, the album, disc and track number must match too");
+                sa.checkbox(&mut self.scan_options.integrity_check, "Audio: check the integrity of duplicates by decoding them")
+                    .on_hover_text("Slow. Finds decode errors, truncated streams and wrong durations");
+                sa.add_space(10.0);
+
+                sa.heading("Match profiles");
+                self.profiles_ui(sa);
+                sa.add_space(10.0);
+
+                sa.heading("Title normalization");
+                self.normalize_ui(sa);
             });
         ui.add_space(20.0);
 
@@ -142,4 +230,121 @@ impl SettingsUI {
                 } // for group
             }); // body
     }
+
+    /// Renders the editor for the METADATA match profiles
+    fn profiles_ui(&mut self, ui: &mut egui::Ui) {
+        self.edited_profile = self.edited_profile.min(self.match_profiles.len().saturating_sub(1));
+
+        ui.horizontal(|ui| {
+            let selected = self.match_profiles.get(self.edited_profile).map(|p| p.name.clone()).unwrap_or_default();
+            egui::ComboBox::from_id_source("edited_profile")
+                .selected_text(selected)
+                .show_ui(ui, |ui| {
+                    for (i, profile) in self.match_profiles.iter().enumerate() {
+                        ui.selectable_value(&mut self.edited_profile, i, &profile.name);
+                    }
+                });
+            if ui.button("New").clicked() {
+                self.match_profiles.push(MatchProfile::new(&format!("Profile {}", self.match_profiles.len() + 1)));
+                self.edited_profile = self.match_profiles.len() - 1;
+            }
+            if ui.add_enabled(self.match_profiles.len() > 1, egui::Button::new("Delete")).clicked() {
+                self.match_profiles.remove(self.edited_profile);
+                self.edited_profile = self.edited_profile.saturating_sub(1);
+            }
+            if ui.button("Save").clicked() {
+                self.profile_status = match save_profiles(&self.match_profiles) {
+                    Ok(()) => "Profiles saved".to_string(),
+                    Err(e) => format!("Could not save the profiles: {}", e),
+                };
+            }
+            ui.label(&self.profile_status);
+        });
+
+        let profile = match self.match_profiles.get_mut(self.edited_profile) {
+            Some(profile) => profile,
+            None => return,
+        };
+        ui.horizontal(|ui| {
+            ui.label("Name:");
+            ui.text_edit_singleline(&mut profile.name);
+        });
+
+        let mut remove: Option<usize> = None;
+        egui::Grid::new("profile_fields").num_columns(5).striped(true).show(ui, |ui| {
+            ui.strong("Keys (alternatives separated by '|')");
+            ui.strong("");
+            ui.strong("Normalization");
+            ui.strong("Required");
+            ui.end_row();
+
+            for (i, field) in profile.fields.iter_mut().enumerate() {
+                ui.text_edit_singleline(&mut field.keys);
+                egui::ComboBox::from_id_source(("field_key", i))
+                    .selected_text("Add key")
+                    .show_ui(ui, |ui| {
+                        for key in MATCH_KEYS.iter() {
+                            if ui.selectable_label(false, *key).clicked() {
+                                if !field.keys.is_empty() {
+                                    field.keys.push('|');
+                                }
+                                field.keys.push_str(key);
+                            }
+                        }
+                    });
+                egui::ComboBox::from_id_source(("field_normalization", i))
+                    .selected_text(field.normalization.name())
+                    .show_ui(ui, |ui| {
+                        for normalization in Normalization::ALL {
+                            ui.selectable_value(&mut field.normalization, normalization, normalization.name());
+                        }
+                    });
+                ui.checkbox(&mut field.required, "");
+                if ui.button("\u{1F5D1}").on_hover_text("Remove field").clicked() {
+                    remove = Some(i);
+                }
+                ui.end_row();
+            }
+        });
+        if let Some(i) = remove {
+            profile.fields.remove(i);
+        }
+        if ui.button("Add field").clicked() {
+            profile.fields.push(MatchField::new("", Normalization::NONE, false));
+        }
+    }
+
+    /// Renders the normalization rules of 'normalize' fields with a live preview
+    fn normalize_ui(&mut self, ui: &mut egui::Ui) {
+        let rules = &mut self.scan_options.normalize_rules;
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut rules.nfkc, "Unicode NFKC");
+            ui.checkbox(&mut rules.fold_diacritics, "Fold diacritics");
+            ui.checkbox(&mut rules.remove_feat, "Remove 'feat.'");
+            ui.checkbox(&mut rules.strip_brackets, "Strip brackets");
+        });
+        ui.horizontal(|ui| {
+            ui.label("Keep brackets containing:");
+            ui.add_enabled(rules.strip_brackets, egui::TextEdit::singleline(&mut rules.keep_words).desired_width(f32::INFINITY));
+        });
+        ui.label("Regex rules (one 'pattern => replacement' per line)");
+        ui.add(egui::TextEdit::multiline(&mut rules.regex_rules)
+            .hint_text("^the\\s+ =>\n\\bpt\\.? => part")
+            .code_editor()
+            .desired_rows(3));
+
+        ui.horizontal(|ui| {
+            ui.label("Preview:");
+            ui.text_edit_singleline(&mut self.normalize_preview);
+            match rules.compile() {
+                Ok(()) => {
+                    ui.label("\u{2192}");
+                    ui.strong(rules.normalize(&self.normalize_preview));
+                }
+                Err(e) => {
+                    ui.colored_label(ui.visuals().error_fg_color, format!("Invalid rule: {}", e));
+                }
+            }
+        });
+    }
 }
@@ -0,0 +1,278 @@
+use crate::components::basic::lofty_utils::{get_audio_tags, get_tag_types, write_audio_tags};
+use crate::scanner::options::ScanOptions;
+use crate::scanner::scanner::metadata_clusters;
+
+use anyhow::Error;
+use eframe::egui::{self, Color32, RichText, ScrollArea, TextEdit};
+use lofty::tag::ItemKey;
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    sync::MutexGuard,
+};
+
+/// The editable fields with their label
+const FIELDS: [(ItemKey, &str); 8] = [
+    (ItemKey::TrackTitle, "Title"),
+    (ItemKey::TrackArtist, "Artist"),
+    (ItemKey::AlbumTitle, "Album"),
+    (ItemKey::AlbumArtist, "Album artist"),
+    (ItemKey::TrackNumber, "Track"),
+    (ItemKey::DiscNumber, "Disc"),
+    (ItemKey::Year, "Year"),
+    (ItemKey::Genre, "Genre"),
+];
+
+/// A changed field of a file: index into FIELDS, old and new value
+type Change = (usize, String, String);
+
+/// Editor for the tags of the selected rows of the duplicates table.
+///
+/// The changes are previewed per file before they are written, with the tags of the file
+/// they are written to. All tags are changed, so no old value is left in another tag. After
+/// saving, the tags are read again and the groups of the edited files are rebuilt with the
+/// matcher of the last scan, so a mis-tagged file leaves its group immediately. The editor is
+/// only available after a METADATA scan.
+pub struct TagEditor {
+    open: bool,
+    paths: Vec<String>,
+    originals: Vec<HashMap<String, String>>,
+    tag_types: Vec<String>, // The tags of each file, i.e. 'Id3v2, Ape'
+    values: Vec<String>, // Edited value of each field
+    mixed: Vec<bool>,    // The selected files have different values. An empty value keeps them
+    status: String,
+    pub options: Option<ScanOptions>, // Options of the last METADATA scan, used to rebuild the groups
+}
+
+impl TagEditor {
+    pub fn new() -> Self {
+        Self {
+            open: false,
+            paths: Vec::new(),
+            originals: Vec::new(),
+            tag_types: Vec::new(),
+            values: Vec::new(),
+            mixed: Vec::new(),
+            status: String::new(),
+            options: None,
+        }
+    }
+
+    /// The groups can only be rebuilt with the options of a METADATA scan
+    pub fn is_available(&self) -> bool {
+        self.options.is_some()
+    }
+
+    /// Open the editor for the given rows of the duplicates table
+    pub fn open(&mut self, duplicates: &[HashMap<String, String>], rows: &[usize]) {
+        if !self.is_available() {
+            return;
+        }
+        self.originals = rows.iter().map(|idx| duplicates[*idx].clone()).collect();
+        self.paths = self.originals.iter().map(|map| map.get("PATH").cloned().unwrap_or_default()).collect();
+        self.tag_types = self
+            .paths
+            .iter()
+            .map(|path| get_tag_types(Path::new(path)).map_or_else(|e| e.to_string(), |types| types.join(", ")))
+            .collect();
+        self.values.clear();
+        self.mixed.clear();
+        for (key, _) in FIELDS.iter() {
+            let name = format!("{:?}", key);
+            let values: HashSet<&str> = self.originals.iter().map(|map| map.get(&name).map_or("", |v| v.as_str())).collect();
+            let mixed = values.len() > 1;
+            self.values.push(if mixed { String::new() } else { values.into_iter().next().unwrap_or("").to_string() });
+            self.mixed.push(mixed);
+        }
+        self.status.clear();
+        self.open = true;
+    }
+
+    /// The changes of each file, files without changes are left out
+    fn changes(&self) -> Vec<(usize, Vec<Change>)> {
+        let mut changes = Vec::new();
+        for (file, original) in self.originals.iter().enumerate() {
+            let mut file_changes = Vec::new();
+            for (field, (key, _)) in FIELDS.iter().enumerate() {
+                let new = self.values[field].trim();
+                if self.mixed[field] && new.is_empty() {
+                    continue;
+                }
+                let old = original.get(&format!("{:?}", key)).map_or("", |v| v.as_str());
+                if old != new {
+                    file_changes.push((field, old.to_string(), new.to_string()));
+                }
+            }
+            if !file_changes.is_empty() {
+                changes.push((file, file_changes));
+            }
+        }
+        changes
+    }
+
+    /// Show the editor window if it is open
+    pub fn show(
+        &mut self,
+        ctx: &egui::Context,
+        duplicates: &mut MutexGuard<Vec<HashMap<String, String>>>,
+        checked: &mut MutexGuard<Vec<bool>>,
+    ) {
+        let mut open = self.open;
+        let mut close = false;
+        egui::Window::new("\u{270F} Edit tags")
+            .open(&mut open)
+            .resizable(true)
+            .show(ctx, |ui| {
+                ui.label(format!("{} file(s) selected", self.paths.len()));
+                ui.separator();
+
+                egui::Grid::new("tag_editor_grid").num_columns(2).show(ui, |ui| {
+                    for (field, (_, label)) in FIELDS.iter().enumerate() {
+                        ui.label(*label);
+                        let mut edit = TextEdit::singleline(&mut self.values[field]).desired_width(300.0);
+                        if self.mixed[field] {
+                            edit = edit.hint_text("(multiple values)");
+                        }
+                        ui.add(edit);
+                        ui.end_row();
+                    }
+                });
+                ui.separator();
+
+                // Preview of the changes
+                let changes = self.changes();
+                ui.strong("Changes");
+                ScrollArea::vertical().max_height(200.0).auto_shrink([false, true]).show(ui, |ui| {
+                    if changes.is_empty() {
+                        ui.label("No changes");
+                    }
+                    for (file, file_changes) in changes.iter() {
+                        ui.label(RichText::new(format!("{} ({})", self.paths[*file], self.tag_types[*file])).color(Color32::GRAY));
+                        for (field, old, new) in file_changes.iter() {
+                            ui.horizontal(|ui| {
+                                ui.add_space(15.0);
+                                ui.label(format!("{}:", FIELDS[*field].1));
+                                ui.label(RichText::new(format!("'{}'", old)).strikethrough());
+                                ui.label(format!("\u{2192} '{}'", new));
+                            });
+                        }
+                    }
+                });
+                ui.separator();
+
+                ui.horizontal(|ui| {
+                    if ui.add_enabled(!changes.is_empty(), egui::Button::new("Save")).clicked() {
+                        self.save(&changes, duplicates, checked);
+                        close = self.status.is_empty();
+                    }
+                    if ui.button("Cancel").clicked() {
+                        close = true;
+                    }
+                });
+                if !self.status.is_empty() {
+                    ui.label(RichText::new(&self.status).color(Color32::LIGHT_RED));
+                }
+            });
+        self.open = open && !close;
+    }
+
+    /// Write the changes, update the rows of the edited files and rebuild their groups
+    fn save(
+        &mut self,
+        changes: &[(usize, Vec<Change>)],
+        duplicates: &mut MutexGuard<Vec<HashMap<String, String>>>,
+        checked: &mut MutexGuard<Vec<bool>>,
+    ) {
+        let mut errors = Vec::new();
+        let mut groups: HashSet<String> = HashSet::new();
+        for (file, file_changes) in changes.iter() {
+            let path = Path::new(&self.paths[*file]);
+            let items: Vec<(ItemKey, String)> =
+                file_changes.iter().map(|(field, _, new)| (FIELDS[*field].0.clone(), new.clone())).collect();
+            match write_audio_tags(path, &items).and_then(|_| refresh_row(duplicates, path)) {
+                Ok(Some(tags)) => {
+                    groups.extend(tags.get("GROUP").cloned());
+                    self.originals[*file] = tags;
+                }
+                Ok(None) => (),
+                Err(e) => errors.push(format!("{}: {}", path.display(), e)),
+            }
+        }
+        if let Some(options) = &self.options {
+            regroup(duplicates, checked, &groups, options);
+        }
+        self.status = errors.join("\n");
+    }
+}
+
+/// Read the tags of a file again and replace them in its row of the duplicates table. The
+/// result entries of the scan, i.e. GROUP and QUALITY, are kept. Returns the new row.
+pub fn refresh_row(
+    duplicates: &mut MutexGuard<Vec<HashMap<String, String>>>,
+    path: &Path,
+) -> Result<Option<HashMap<String, String>>, Error> {
+    let mut tags = get_audio_tags(path)?;
+    let row = match duplicates.iter().position(|map| map.get("PATH").map(Path::new) == Some(path)) {
+        Some(row) => row,
+        None => return Ok(None),
+    };
+    for (key, value) in duplicates[row].iter() {
+        if !key.chars().any(|c| c.is_lowercase()) && !tags.contains_key(key) {
+            tags.insert(key.clone(), value.clone());
+        }
+    }
+    duplicates[row] = tags.clone();
+    Ok(Some(tags))
+}
+
+/// Rebuild the given groups with the matcher of the METADATA scan, see 'metadata_clusters'.
+///
+/// In reference mode the members must match the reference copy. Otherwise the largest
+/// cluster of matching members keeps the group, other clusters get a new group. Members which
+/// don't match any other member are removed.
+fn regroup(
+    duplicates: &mut MutexGuard<Vec<HashMap<String, String>>>,
+    checked: &mut MutexGuard<Vec<bool>>,
+    groups: &HashSet<String>,
+    options: &ScanOptions,
+) {
+    let mut next_group = duplicates.iter().filter_map(|map| map.get("GROUP")?.parse::<usize>().ok()).max().unwrap_or(0) + 1;
+    let mut remove: Vec<usize> = Vec::new();
+
+    for group in groups.iter() {
+        let members: Vec<usize> = (0..duplicates.len()).filter(|idx| duplicates[*idx].get("GROUP") == Some(group)).collect();
+
+        if let Some(reference) = members.first().and_then(|idx| duplicates[*idx].get("REFERENCE")) {
+            // The reference copy is the first file, members outside its cluster are removed
+            let reference_tags = get_audio_tags(Path::new(reference)).unwrap_or_default();
+            let mut tags: Vec<&HashMap<String, String>> = vec![&reference_tags];
+            tags.extend(members.iter().map(|idx| &duplicates[*idx]));
+            let clusters = metadata_clusters(&tags, options, || false);
+            let matching = clusters.iter().find(|cluster| cluster.contains(&0));
+            for (i, idx) in members.iter().enumerate() {
+                if !matching.is_some_and(|cluster| cluster.contains(&(i + 1))) {
+                    remove.push(*idx);
+                }
+            }
+            continue;
+        }
+
+        let tags: Vec<&HashMap<String, String>> = members.iter().map(|idx| &duplicates[*idx]).collect();
+        let mut clusters = metadata_clusters(&tags, options, || false);
+        clusters.sort_by_key(|cluster| std::cmp::Reverse(cluster.len()));
+        let clustered: HashSet<usize> = clusters.iter().flatten().copied().collect();
+        remove.extend((0..members.len()).filter(|i| !clustered.contains(i)).map(|i| members[i]));
+        for cluster in clusters.iter().skip(1) {
+            for i in cluster.iter() {
+                duplicates[members[*i]].insert("GROUP".to_string(), next_group.to_string());
+            }
+            next_group += 1;
+        }
+    }
+
+    remove.sort();
+    for idx in remove.into_iter().rev() {
+        duplicates.remove(idx);
+        checked.remove(idx);
+    }
+}
@@ -0,0 +1,216 @@
+use crate::components::basic::lofty_utils::{get_audio_tags, write_audio_tags};
+use crate::components::basic::tag_schema::item_key;
+use crate::components::duplicates_table::delete_checked_duplicates;
+use crate::components::tag_editor::refresh_row;
+
+use eframe::egui::{self, Color32, RichText, ScrollArea};
+use lofty::tag::ItemKey;
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::Path,
+    sync::MutexGuard,
+};
+
+const MAX_VALUE_CHARS: usize = 40;
+
+/// Merge the tags of the deleted copies of each group into the kept copy before they are
+/// deleted.
+///
+/// The kept copy is the reference copy in reference mode, otherwise the only unchecked member
+/// of the group. Groups without a single kept copy or with unreadable tags are skipped. All
+/// writable fields of the members are compared side by side and a value is picked per field.
+/// Fields which are empty in the kept copy are filled from the first deleted copy that has
+/// them, i.e. MusicBrainz IDs, lyrics or ReplayGain. The checked files are deleted after the
+/// last group, cancelling keeps all files.
+pub struct TagMerge {
+    pub enabled: bool, // Merge before deleting, set in the delete dialog
+    open: bool,
+    queue: Vec<String>, // Groups which are not shown yet
+    group: String,
+    paths: Vec<String>, // The kept copy first
+    tags: Vec<HashMap<String, String>>,
+    fields: Vec<(String, ItemKey)>,
+    selected: Vec<usize>, // Member whose value is used for each field
+    only_differing: bool,
+    status: String,
+}
+
+impl TagMerge {
+    pub fn new() -> Self {
+        Self {
+            enabled: true,
+            open: false,
+            queue: Vec::new(),
+            group: String::new(),
+            paths: Vec::new(),
+            tags: Vec::new(),
+            fields: Vec::new(),
+            selected: Vec::new(),
+            only_differing: true,
+            status: String::new(),
+        }
+    }
+
+    /// Start merging the groups with checked members. Returns false if there is no group to
+    /// merge, the checked files can be deleted right away then.
+    pub fn start(&mut self, duplicates: &[HashMap<String, String>], checked: &[bool]) -> bool {
+        self.queue.clear();
+        for (map, _) in duplicates.iter().zip(checked.iter()).filter(|(_, checked)| **checked) {
+            if let Some(group) = map.get("GROUP").filter(|group| !self.queue.contains(group)) {
+                self.queue.push(group.clone());
+            }
+        }
+        self.queue.reverse(); // Popped from the end
+        self.status.clear();
+        self.open = self.next_group(duplicates, checked);
+        self.open
+    }
+
+    /// Load the next group of the queue which can be merged. Returns false if there is none.
+    fn next_group(&mut self, duplicates: &[HashMap<String, String>], checked: &[bool]) -> bool {
+        while let Some(group) = self.queue.pop() {
+            if self.load(duplicates, checked, group) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Read the tags of the kept and the deleted copies of a group. Returns false if the group
+    /// has no single kept copy or a file has no readable tags.
+    fn load(&mut self, duplicates: &[HashMap<String, String>], checked: &[bool], group: String) -> bool {
+        self.paths.clear();
+        self.tags.clear();
+        self.fields.clear();
+        self.selected.clear();
+
+        let members: Vec<usize> = (0..duplicates.len()).filter(|idx| duplicates[*idx].get("GROUP") == Some(&group)).collect();
+        let path = |idx: &usize| duplicates[*idx].get("PATH").cloned().unwrap_or_default();
+        let kept: Vec<String> = match members.first().and_then(|idx| duplicates[*idx].get("REFERENCE")) {
+            Some(reference) => vec![reference.clone()],
+            None => members.iter().filter(|idx| !checked[**idx]).map(path).collect(),
+        };
+        if kept.len() != 1 {
+            return false;
+        }
+
+        for path in kept.into_iter().chain(members.iter().filter(|idx| checked[**idx]).map(path)) {
+            match get_audio_tags(Path::new(&path)) {
+                Ok(tags) => self.tags.push(tags),
+                Err(_) => return false,
+            }
+            self.paths.push(path);
+        }
+        self.group = group;
+
+        let names: BTreeSet<&String> = self.tags.iter().flat_map(|tags| tags.keys()).collect();
+        for name in names {
+            let binary = self.tags.iter().any(|tags| tags.get(name).is_some_and(|v| v.starts_with("<binary")));
+            if let (Some(key), false) = (item_key(name), binary) {
+                self.fields.push((name.clone(), key));
+            }
+        }
+        self.selected = self
+            .fields
+            .iter()
+            .map(|(name, _)| self.tags.iter().position(|tags| tags.get(name).is_some_and(|v| !v.is_empty())).unwrap_or(0))
+            .collect();
+        true
+    }
+
+    /// Value of a field in a member, empty if the member doesn't have it
+    fn value(&self, member: usize, field: usize) -> &str {
+        self.tags[member].get(&self.fields[field].0).map_or("", |v| v.as_str())
+    }
+
+    /// The fields whose picked value differs from the value of the kept copy
+    fn changes(&self) -> Vec<(ItemKey, String)> {
+        (0..self.fields.len())
+            .filter(|field| self.value(self.selected[*field], *field) != self.value(0, *field))
+            .map(|field| (self.fields[field].1.clone(), self.value(self.selected[field], field).to_string()))
+            .collect()
+    }
+
+    /// Show the merge window if it is open. After the last group the checked files are deleted.
+    pub fn show(
+        &mut self,
+        ctx: &egui::Context,
+        duplicates: &mut MutexGuard<Vec<HashMap<String, String>>>,
+        checked: &mut MutexGuard<Vec<bool>>,
+    ) {
+        let mut open = self.open;
+        let mut close = false;
+        let mut next = false;
+        egui::Window::new(format!("\u{1F500} Merge tags of group {} before deleting", self.group))
+            .open(&mut open)
+            .resizable(true)
+            .show(ctx, |ui| {
+                if !self.paths.is_empty() {
+                    ui.checkbox(&mut self.only_differing, "Only fields with different values");
+                    ui.separator();
+
+                    ScrollArea::both().max_height(400.0).auto_shrink([false, true]).show(ui, |ui| {
+                        egui::Grid::new("tag_merge_grid").striped(true).num_columns(self.paths.len() + 1).show(ui, |ui| {
+                            ui.strong("Field");
+                            for (member, path) in self.paths.iter().enumerate() {
+                                let name = Path::new(path).file_name().and_then(|n| n.to_str()).unwrap_or(path);
+                                let title = if member == 0 { format!("Kept: {}", name) } else { format!("Deleted: {}", name) };
+                                ui.strong(title).on_hover_text(path);
+                            }
+                            ui.end_row();
+
+                            for field in 0..self.fields.len() {
+                                let differing = (1..self.paths.len()).any(|member| self.value(member, field) != self.value(0, field));
+                                if self.only_differing && !differing {
+                                    continue;
+                                }
+                                ui.label(&self.fields[field].0);
+                                for member in 0..self.paths.len() {
+                                    let value = self.value(member, field).to_string();
+                                    let text = if value.is_empty() {
+                                        "(empty)".to_string()
+                                    } else if value.chars().count() > MAX_VALUE_CHARS {
+                                        format!("{}...", value.chars().take(MAX_VALUE_CHARS).collect::<String>())
+                                    } else {
+                                        value.clone()
+                                    };
+                                    ui.radio_value(&mut self.selected[field], member, text).on_hover_text(value);
+                                }
+                                ui.end_row();
+                            }
+                        });
+                    });
+                    ui.separator();
+                }
+
+                let changes = self.changes();
+                ui.horizontal(|ui| {
+                    let merge = egui::Button::new(format!("Merge {} field(s) into kept copy", changes.len()));
+                    if ui.add_enabled(!changes.is_empty(), merge).clicked() {
+                        let path = Path::new(&self.paths[0]);
+                        match write_audio_tags(path, &changes).and_then(|_| refresh_row(duplicates, path)) {
+                            Ok(_) => next = true,
+                            Err(e) => self.status = format!("Could not write the tags of {}: {}", path.display(), e),
+                        }
+                    }
+                    if ui.button("Skip group").clicked() {
+                        next = true;
+                    }
+                    if ui.button("Cancel").on_hover_text("Keep all files").clicked() {
+                        close = true;
+                    }
+                });
+                if !self.status.is_empty() {
+                    ui.label(RichText::new(&self.status).color(Color32::LIGHT_RED));
+                }
+            });
+        if next {
+            self.status.clear();
+            if !self.next_group(duplicates, checked) {
+                delete_checked_duplicates(duplicates, checked);
+                close = true;
+            }
+        }
+        self.open = open && !close;
+    }
+}
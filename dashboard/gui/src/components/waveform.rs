@@ -0,0 +1,165 @@
+use crate::components::basic::audio_utils::{get_envelope, Envelope};
+
+use eframe::egui::{self, pos2, vec2, Align2, Color32, FontId, Sense, Stroke};
+use std::{
+    collections::{HashMap, VecDeque},
+    path::Path,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+const INTERVAL_MS: u32 = 50;
+const WAVEFORM_HEIGHT: f32 = 40.0;
+const MAX_WORKERS: usize = 2;
+
+#[derive(Clone)]
+enum Waveform {
+    Loading,
+    Ready(Arc<Envelope>),
+    Failed(String),
+}
+
+/// State shared with the decoding threads
+struct Shared {
+    waveforms: HashMap<String, Waveform>,
+    queue: VecDeque<String>, // Requested files which are not decoded yet
+    generation: u64,         // Incremented by clear(), older results are dropped
+    workers: usize,
+}
+
+/// Amplitude envelopes of audio files, decoded on background threads.
+///
+/// An envelope is decoded when it is requested the first time and is kept until the cache is
+/// cleared, i.e. by a new scan. The requests are queued and decoded by at most MAX_WORKERS
+/// threads, which end when the queue is empty.
+#[derive(Clone)]
+pub struct WaveformCache {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl WaveformCache {
+    pub fn new() -> Self {
+        Self {
+            shared: Arc::new(Mutex::new(Shared {
+                waveforms: HashMap::new(),
+                queue: VecDeque::new(),
+                generation: 0,
+                workers: 0,
+            })),
+        }
+    }
+
+    /// Remove all waveforms and pending requests. Files being decoded are not stored.
+    pub fn clear(&self) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.waveforms.clear();
+        shared.queue.clear();
+        shared.generation += 1;
+    }
+
+    /// Returns the waveform of a file and queues it for decoding if it was not requested before
+    fn get(&self, path: &str) -> Waveform {
+        let mut shared = self.shared.lock().unwrap();
+        if let Some(waveform) = shared.waveforms.get(path) {
+            return waveform.clone();
+        }
+        shared.waveforms.insert(path.to_string(), Waveform::Loading);
+        shared.queue.push_back(path.to_string());
+
+        if shared.workers < MAX_WORKERS {
+            shared.workers += 1;
+            let shared = self.shared.clone();
+            thread::spawn(move || decode_queue(&shared));
+        }
+        Waveform::Loading
+    }
+}
+
+/// Decode the queued files until the queue is empty
+fn decode_queue(shared: &Mutex<Shared>) {
+    loop {
+        let (path, generation) = {
+            let mut state = shared.lock().unwrap();
+            match state.queue.pop_front() {
+                Some(path) => (path, state.generation),
+                None => {
+                    state.workers -= 1;
+                    return;
+                }
+            }
+        };
+
+        let waveform = match get_envelope(Path::new(&path), INTERVAL_MS) {
+            Ok(envelope) => Waveform::Ready(Arc::new(envelope)),
+            Err(e) => Waveform::Failed(e.to_string()),
+        };
+        let mut state = shared.lock().unwrap();
+        if state.generation == generation {
+            state.waveforms.insert(path, waveform);
+        }
+    }
+}
+
+/// Show the envelopes of the given files stacked on a common time axis. The end of each file
+/// is marked with its duration. Selected files are drawn in the error color.
+pub fn waveforms_ui(ui: &mut egui::Ui, cache: &WaveformCache, files: &[(&str, bool)], width: f32) {
+    let waveforms: Vec<Waveform> = files.iter().map(|(path, _)| cache.get(path)).collect();
+    let longest = waveforms
+        .iter()
+        .filter_map(|w| match w {
+            Waveform::Ready(envelope) => Some(envelope.duration_ms),
+            _ => None,
+        })
+        .max()
+        .unwrap_or(0)
+        .max(1);
+
+    ui.vertical(|ui| {
+        for ((path, selected), waveform) in files.iter().zip(waveforms.iter()) {
+            let name = Path::new(path).file_name().and_then(|n| n.to_str()).unwrap_or(path);
+            ui.label(egui::RichText::new(name).small());
+
+            let (response, painter) = ui.allocate_painter(vec2(width, WAVEFORM_HEIGHT), Sense::hover());
+            let rect = response.rect;
+            painter.rect_filled(rect, 2.0, ui.visuals().extreme_bg_color);
+            let font = FontId::proportional(11.0);
+            let text_color = ui.visuals().text_color();
+
+            match waveform {
+                Waveform::Loading => {
+                    painter.text(rect.center(), Align2::CENTER_CENTER, "Decoding...", font, text_color);
+                }
+                Waveform::Failed(e) => {
+                    painter.text(rect.center(), Align2::CENTER_CENTER, e, font, ui.visuals().error_fg_color);
+                }
+                Waveform::Ready(envelope) => {
+                    let color = if *selected { ui.visuals().error_fg_color } else { Color32::LIGHT_BLUE };
+                    let ms_per_pixel = longest as f32 / width;
+                    let end = rect.left() + envelope.duration_ms as f32 / ms_per_pixel;
+
+                    // One vertical line per pixel column with the highest peak of its time range
+                    for x in 0..(end - rect.left()).ceil() as usize {
+                        let first = (x as f32 * ms_per_pixel / envelope.interval_ms as f32) as usize;
+                        let last = (((x + 1) as f32 * ms_per_pixel / envelope.interval_ms as f32) as usize).max(first + 1);
+                        let peak = envelope.peaks.iter().skip(first).take(last - first).fold(0.0f32, |a, b| a.max(*b));
+                        let height = peak.min(1.0) * rect.height() / 2.0;
+                        let x = rect.left() + x as f32 + 0.5;
+                        painter.line_segment([pos2(x, rect.center().y - height), pos2(x, rect.center().y + height)], Stroke::new(1.0, color));
+                    }
+
+                    // Mark the end of the file with its duration
+                    painter.line_segment([pos2(end, rect.top()), pos2(end, rect.bottom())], Stroke::new(1.0, text_color));
+                    let seconds = envelope.duration_ms as f32 / 1000.0;
+                    let duration = format!("{}:{:04.1}", (seconds / 60.0) as u32, seconds % 60.0);
+                    let (anchor, x) = if end > rect.right() - 40.0 { (Align2::RIGHT_TOP, end - 2.0) } else { (Align2::LEFT_TOP, end + 2.0) };
+                    painter.text(pos2(x, rect.top()), anchor, duration, font, text_color);
+                }
+            }
+        }
+    });
+
+    if waveforms.iter().any(|w| matches!(w, Waveform::Loading)) {
+        ui.ctx().request_repaint_after(Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,171 @@
+use crate::components::basic::tag_schema::{tag_number, tag_value};
+use crate::scanner::messenger::Finding;
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    path::Path,
+};
+
+/// Track numbers and totals above are considered wrong and ignored
+const MAX_TRACKS: u32 = 999;
+
+/// Album artist, or the directory of albums without album artist, album title and disc
+type DiscKey<'a> = (Option<&'a str>, Option<&'a Path>, &'a str, u32);
+
+/// The tracks of one disc of an album found in one directory
+struct Part<'a> {
+    directory: &'a Path,
+    tracks: Vec<u32>,
+}
+
+/// Check the completeness of the albums of the scanned files.
+///
+/// Tracks are grouped by album artist, album title and disc. Albums without album artist are
+/// grouped by directory and album title, as their track artists may differ. Reported are gaps
+/// in the track numbering up to the highest track number or the 'TrackTotal', duplicate
+/// track numbers and discs whose tracks are split across several directories. For split discs
+/// each directory is listed with its tracks, to decide which partial copy to keep. Track
+/// numbers and totals above MAX_TRACKS and totals below the highest track are ignored.
+pub fn check_albums<'a>(files: impl Iterator<Item = (&'a Path, &'a HashMap<String, String>)>) -> Vec<Finding> {
+    let mut discs: BTreeMap<DiscKey, Vec<Part>> = BTreeMap::new();
+    let mut totals: HashMap<DiscKey, u32> = HashMap::new();
+    for (path, tags) in files {
+        let Some(album) = tag_value(tags, "AlbumTitle") else { continue };
+        let Some((track, track_total)) = tag_value(tags, "TrackNumber").and_then(tag_number) else { continue };
+        if track == 0 || track > MAX_TRACKS {
+            continue;
+        }
+        let disc = tag_value(tags, "DiscNumber").and_then(tag_number).map_or(1, |(disc, _)| disc);
+
+        let directory = path.parent().unwrap_or(Path::new(""));
+        let artist = tag_value(tags, "AlbumArtist");
+        let key = (artist, if artist.is_none() { Some(directory) } else { None }, album, disc);
+        let total = track_total.or_else(|| tag_value(tags, "TrackTotal").and_then(tag_number).map(|(total, _)| total));
+        if let Some(total) = total.filter(|total| *total <= MAX_TRACKS) {
+            let entry = totals.entry(key).or_default();
+            *entry = (*entry).max(total);
+        }
+
+        let parts = discs.entry(key).or_default();
+        match parts.iter_mut().find(|part| part.directory == directory) {
+            Some(part) => part.tracks.push(track),
+            None => parts.push(Part { directory, tracks: vec![track] }),
+        }
+    }
+
+    let mut findings = Vec::new();
+    for (key, parts) in discs.iter() {
+        let (artist, _, album, disc) = key;
+        let name = match artist {
+            Some(artist) => format!("'{}' by {}, disc {}", album, artist, disc),
+            None => format!("'{}', disc {}", album, disc),
+        };
+        let path = parts[0].directory.to_str().unwrap_or("");
+        let tracks: Vec<u32> = parts.iter().flat_map(|part| part.tracks.iter().copied()).collect();
+        let highest = tracks.iter().copied().max().unwrap_or(0);
+        let total = totals.get(key).copied().filter(|total| *total >= highest).unwrap_or(highest);
+
+        let missing = missing_tracks(&tracks, total);
+        if !missing.is_empty() {
+            let message = format!("{}: {} of {} tracks, missing {}", name, total as usize - missing.len(), total, list(&missing));
+            findings.push(Finding::new("Incomplete", path, &message));
+        }
+
+        let mut counts: BTreeMap<u32, usize> = BTreeMap::new();
+        tracks.iter().for_each(|track| *counts.entry(*track).or_default() += 1);
+        let duplicates: Vec<u32> = counts.into_iter().filter(|(_, count)| *count > 1).map(|(track, _)| track).collect();
+        if !duplicates.is_empty() {
+            findings.push(Finding::new("Duplicate track", path, &format!("{}: duplicate track numbers {}", name, list(&duplicates))));
+        }
+
+        if parts.len() > 1 {
+            let directories: Vec<String> = parts
+                .iter()
+                .map(|part| {
+                    let missing = missing_tracks(&part.tracks, total);
+                    let state = if missing.is_empty() { "complete".to_string() } else { format!("missing {}", list(&missing)) };
+                    format!("{} ({} tracks, {})", part.directory.to_str().unwrap_or(""), part.tracks.len(), state)
+                })
+                .collect();
+            let message = format!("{}: split across {} directories: {}", name, parts.len(), directories.join("; "));
+            findings.push(Finding::new("Split album", path, &message));
+        }
+    }
+    findings
+}
+
+/// Track numbers from 1 to 'total' which are not in 'tracks'
+fn missing_tracks(tracks: &[u32], total: u32) -> Vec<u32> {
+    let present: BTreeSet<u32> = tracks.iter().copied().collect();
+    (1..=total).filter(|track| !present.contains(track)).collect()
+}
+
+/// Join track numbers, consecutive numbers as a range i.e. '2-5, 9'
+fn list(tracks: &[u32]) -> String {
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+    for track in tracks.iter().copied() {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == track => *end = track,
+            _ => ranges.push((track, track)),
+        }
+    }
+    let ranges: Vec<String> = ranges
+        .iter()
+        .map(|(start, end)| if start == end { start.to_string() } else { format!("{}-{}", start, end) })
+        .collect();
+    ranges.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(artist: Option<&str>, track: &str) -> HashMap<String, String> {
+        let mut tags = HashMap::new();
+        tags.insert("AlbumTitle".to_string(), "Album".to_string());
+        tags.insert("TrackArtist".to_string(), "Track artist".to_string());
+        tags.insert("TrackNumber".to_string(), track.to_string());
+        if let Some(artist) = artist {
+            tags.insert("AlbumArtist".to_string(), artist.to_string());
+        }
+        tags
+    }
+
+    fn check(files: &[(&str, HashMap<String, String>)]) -> Vec<(String, String)> {
+        let files = files.iter().map(|(path, tags)| (Path::new(*path), tags));
+        check_albums(files).into_iter().map(|f| (f.category, f.message)).collect()
+    }
+
+    #[test]
+    fn reports_missing_tracks_up_to_the_total() {
+        let findings = check(&[("a/1.mp3", track(Some("X"), "1/6")), ("a/3.mp3", track(Some("X"), "3"))]);
+        assert_eq!(findings, vec![("Incomplete".to_string(), "'Album' by X, disc 1: 2 of 6 tracks, missing 2, 4-6".to_string())]);
+    }
+
+    #[test]
+    fn ignores_implausible_totals() {
+        let findings = check(&[("a/1.mp3", track(Some("X"), "1/2000")), ("a/2.mp3", track(Some("X"), "2"))]);
+        assert!(findings.is_empty());
+        let findings = check(&[("a/1.mp3", track(Some("X"), "1/1")), ("a/2.mp3", track(Some("X"), "2/1"))]);
+        assert!(findings.is_empty());
+    }
+
+    #[test]
+    fn reports_duplicate_track_numbers() {
+        let findings = check(&[("a/1.mp3", track(Some("X"), "1")), ("a/1b.mp3", track(Some("X"), "01"))]);
+        assert_eq!(findings, vec![("Duplicate track".to_string(), "'Album' by X, disc 1: duplicate track numbers 1".to_string())]);
+    }
+
+    #[test]
+    fn reports_albums_split_across_directories() {
+        let findings = check(&[("a/1.mp3", track(Some("X"), "1")), ("b/2.mp3", track(Some("X"), "2"))]);
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].0, "Split album");
+    }
+
+    #[test]
+    fn albums_without_album_artist_are_grouped_by_directory() {
+        let findings = check(&[("a/1.mp3", track(None, "1")), ("b/1.mp3", track(None, "1"))]);
+        assert!(findings.is_empty());
+    }
+}
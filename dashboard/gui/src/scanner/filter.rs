@@ -0,0 +1,129 @@
+use crate::scanner::options::ScanOptions;
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use walkdir::DirEntry;
+
+/// Decides which entries are skipped while walking the file system.
+///
+/// Only the path and the file type from the directory listing are used, so excluded
+/// entries are never stat'ed and excluded directories are never descended into.
+pub struct PathFilter {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+    ignore_files: Vec<&'static str>,
+    skip_hidden: bool,
+    ignores: HashMap<PathBuf, Option<Gitignore>>, // Parsed ignore files per directory
+}
+
+impl PathFilter {
+    pub fn new(options: &ScanOptions) -> Result<Self, globset::Error> {
+        let include = if options.include_patterns.trim().is_empty() {
+            None
+        } else {
+            Some(build_globset(&options.include_patterns)?)
+        };
+
+        Ok(Self {
+            include,
+            exclude: build_globset(&options.exclude_patterns)?,
+            ignore_files: options.ignore_files(),
+            skip_hidden: options.skip_hidden,
+            ignores: HashMap::new(),
+        })
+    }
+
+    /// Returns true if the entry below 'root' must be skipped
+    pub fn is_excluded(&mut self, entry: &DirEntry, root: &Path) -> bool {
+        if entry.depth() == 0 {
+            return false;
+        }
+        if self.skip_hidden && entry.file_name().to_string_lossy().starts_with('.') {
+            return true;
+        }
+        let is_dir = entry.file_type().is_dir();
+        let relative = entry.path().strip_prefix(root).unwrap_or(entry.path());
+
+        // A trailing slash lets 'dir/**' prune the directory itself
+        let mut candidate = relative.to_string_lossy().replace('\\', "/");
+        if is_dir {
+            candidate.push('/');
+        }
+        if self.exclude.is_match(&candidate) {
+            return true;
+        }
+        // Include patterns select files, directories are always descended into
+        if !is_dir {
+            if let Some(include) = &self.include {
+                if !include.is_match(&candidate) {
+                    return true;
+                }
+            }
+        }
+
+        self.is_ignored(entry.path(), is_dir, root)
+    }
+
+    /// Check the ignore files from the parent directory up to the root.
+    /// The deepest ignore file with a matching rule wins.
+    fn is_ignored(&mut self, path: &Path, is_dir: bool, root: &Path) -> bool {
+        if self.ignore_files.is_empty() {
+            return false;
+        }
+
+        for dir in path.ancestors().skip(1) {
+            if let Some(gitignore) = self.load_ignore(dir) {
+                match gitignore.matched(path, is_dir) {
+                    Match::Ignore(_) => return true,
+                    Match::Whitelist(_) => return false,
+                    Match::None => (),
+                }
+            }
+            if dir == root {
+                break;
+            }
+        }
+        false
+    }
+
+    fn load_ignore(&mut self, dir: &Path) -> Option<&Gitignore> {
+        let ignore_files = &self.ignore_files;
+        self.ignores
+            .entry(dir.to_path_buf())
+            .or_insert_with(|| {
+                let mut builder = GitignoreBuilder::new(dir);
+                let mut found = false;
+                for name in ignore_files.iter() {
+                    let file = dir.join(name);
+                    if file.is_file() {
+                        found = true;
+                        // Invalid lines are skipped, the valid rules of the file still apply
+                        let _ = builder.add(file);
+                    }
+                }
+                if found { builder.build().ok() } else { None }
+            })
+            .as_ref()
+    }
+}
+
+/// Build a GlobSet from a list of patterns separated by newlines.
+///
+/// Like in .gitignore, a pattern without a '/' matches the file name at any depth.
+fn build_globset(patterns: &str) -> Result<GlobSet, globset::Error> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns.lines().map(str::trim).filter(|p| !p.is_empty() && !p.starts_with('#')) {
+        let glob: Glob = if pattern.contains('/') {
+            GlobBuilder::new(pattern.trim_start_matches('/')).literal_separator(true).build()?
+        } else {
+            GlobBuilder::new(&format!("**/{}", pattern)).literal_separator(true).build()?
+        };
+        builder.add(glob);
+    }
+    builder.build()
+}
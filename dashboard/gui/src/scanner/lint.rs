@@ -0,0 +1,63 @@
+use crate::components::basic::tag_schema::tag_value;
+use crate::scanner::messenger::Finding;
+use crate::scanner::options::ScanOptions;
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::Path,
+};
+
+/// Tags every audio file should have
+const ESSENTIAL_TAGS: [&str; 4] = ["TrackTitle", "TrackArtist", "AlbumTitle", "TrackNumber"];
+
+/// Check the tags of a single file: missing essential tags and tags the match profile can't use
+pub fn lint_file(path: &Path, tags: &HashMap<String, String>, options: &ScanOptions) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let path = path.to_str().unwrap_or("");
+
+    let missing: Vec<&str> = ESSENTIAL_TAGS.iter().copied().filter(|key| tag_value(tags, key).is_none()).collect();
+    if !missing.is_empty() {
+        findings.push(Finding::new("Missing", path, &format!("Missing {}", missing.join(", "))));
+    }
+    if let Err(e) = options.match_profile.key(tags, &options.normalize_rules) {
+        findings.push(Finding::new("Key", path, &format!("Profile '{}': {}", options.match_profile.name, e)));
+    }
+    findings
+}
+
+/// Check the tracks of each album folder for inconsistent album artists or years. Tracks are
+/// grouped by folder and album title. Duplicate track numbers are reported by 'check_albums'.
+pub fn lint_albums<'a>(files: impl Iterator<Item = (&'a Path, &'a HashMap<String, String>)>) -> Vec<Finding> {
+    let mut albums: HashMap<(&Path, &str), Vec<&HashMap<String, String>>> = HashMap::new();
+    for (path, tags) in files {
+        let folder = path.parent().unwrap_or(Path::new(""));
+        albums.entry((folder, tag_value(tags, "AlbumTitle").unwrap_or(""))).or_default().push(tags);
+    }
+
+    let mut findings = Vec::new();
+    for ((folder, album), tracks) in albums.iter() {
+        let folder = folder.to_str().unwrap_or("");
+        let name = if album.is_empty() { "Tracks without album".to_string() } else { format!("'{}'", album) };
+
+        // Compilations have no common track artist, but should have a common album artist
+        let artists: BTreeSet<&str> = tracks.iter().filter_map(|tags| tag_value(tags, "AlbumArtist")).collect();
+        if artists.len() > 1 {
+            let list: Vec<&str> = artists.into_iter().collect();
+            findings.push(Finding::new("AlbumArtist", folder, &format!("{}: different album artists {}", name, list.join(" / "))));
+        }
+
+        let years: BTreeSet<&str> = tracks.iter().filter_map(|tags| year(tags)).collect();
+        if years.len() > 1 {
+            let list: Vec<&str> = years.into_iter().collect();
+            findings.push(Finding::new("Year", folder, &format!("{}: different years {}", name, list.join(" / "))));
+        }
+    }
+    findings.sort_by(|a, b| a.path.cmp(&b.path));
+    findings
+}
+
+/// The year of the 'Year' or 'RecordingDate' tag, i.e. '2011' from '2011-04-03'
+fn year(tags: &HashMap<String, String>) -> Option<&str> {
+    let date = tag_value(tags, "Year").or_else(|| tag_value(tags, "RecordingDate"))?;
+    date.get(0..4)
+}
@@ -1,7 +1,13 @@
+#[allow(non_camel_case_types)]
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum ScanType {
     BINARY,
     METADATA,
+    DIRECTORY,
+    IMAGE_SIMILAR,
+    ACOUSTIC,
+    AUDIO_PAYLOAD,
+    LINT,
 }
 
 #[derive(Debug)]
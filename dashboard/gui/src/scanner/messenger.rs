@@ -3,12 +3,31 @@ use crate::scanner::mediatype::Control;
 use std::sync::{Arc, Mutex, MutexGuard};
 use std::collections::HashMap;
 
+/// A single entry of an analysis report, i.e. an empty file
+#[derive(Clone, Debug)]
+pub struct Finding {
+    pub category: String,
+    pub path: String,
+    pub message: String,
+}
+
+impl Finding {
+    pub fn new(category: &str, path: &str, message: &str) -> Self {
+        Self {
+            category: category.to_owned(),
+            path: path.to_owned(),
+            message: message.to_owned(),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Messenger {
     scanner_control: Arc<Mutex<Control>>,
     stdlog: Arc<Mutex<Vec<String>>>,
     errlog: Arc<Mutex<Vec<String>>>,
     reslog: Arc<Mutex<Vec<HashMap<String, String>>>>,
+    reports: Arc<Mutex<Vec<Finding>>>,
     checked: Arc<Mutex<Vec<bool>>>,
     info: Arc<Mutex<String>>,
     progress: Arc<Mutex<f32>>,
@@ -22,6 +41,7 @@ impl Messenger {
             stdlog: Arc::new(Mutex::new(Vec::new())),
             errlog: Arc::new(Mutex::new(Vec::new())),
             reslog: Arc::new(Mutex::new(Vec::new())),
+            reports: Arc::new(Mutex::new(Vec::new())),
             checked: Arc::new(Mutex::new(Vec::new())),
             info: Arc::new(Mutex::new(String::new())),
             progress: Arc::new(Mutex::new(0.0)),
@@ -34,6 +54,7 @@ impl Messenger {
         self.stdlog.lock().unwrap().clear();
         self.errlog.lock().unwrap().clear();
         self.reslog.lock().unwrap().clear();
+        self.reports.lock().unwrap().clear();
         self.checked.lock().unwrap().clear();
         *self.info.lock().unwrap() = "".to_owned();
         *self.progress.lock().unwrap() = 0.0;
@@ -87,6 +108,14 @@ impl Messenger {
         self.checked.lock().unwrap()
     }
 
+    pub fn push_report(&self, finding: Finding) {
+        self.reports.lock().unwrap().push(finding);
+    }
+
+    pub fn reports(&self) -> MutexGuard<Vec<Finding>> {
+        self.reports.lock().unwrap()
+    }
+
     pub fn push_errlog(&self, str: String) {
         self.errlog.lock().unwrap().push(str.clone());
     }
@@ -115,6 +144,10 @@ impl Messenger {
         self.reslog.lock().unwrap().len()
     }
 
+    pub fn cntrep(&self) -> usize {
+        self.reports.lock().unwrap().len()
+    }
+
     pub fn set_progress(&self, max: usize, current: usize, info: &str) {
         *self.progress.lock().unwrap() = current as f32 / max as f32;
         if !info.is_empty() {
@@ -1,3 +1,9 @@
+pub mod album;
+pub mod filter;
+pub mod lint;
 pub mod mediatype;
 pub mod messenger;
+pub mod options;
+pub mod profile;
 pub mod scanner;
+pub mod tree;
@@ -0,0 +1,117 @@
+use crate::components::basic::string_utils::NormalizeRules;
+use crate::scanner::profile::{musicbrainz_key, MatchProfile};
+
+use anyhow::Error;
+use std::collections::HashMap;
+
+/// Options for walking the file system, edited in the Settings tab.
+#[derive(Debug, Clone)]
+pub struct ScanOptions {
+    pub include_patterns: String, // One glob per line. If empty, all files are included
+    pub exclude_patterns: String, // One glob per line
+    pub use_gitignore: bool,      // Honour .gitignore files in the scanned directories
+    pub use_dffignore: bool,      // Honour .dffignore files in the scanned directories
+    pub min_size: u64,            // Smaller files are skipped
+    pub max_size: u64,            // Larger files are skipped. 0 = no limit
+    pub follow_links: bool,       // Follow symbolic links. Loops are detected and reported
+    pub skip_hidden: bool,        // Skip files and directories starting with a '.'
+    pub max_depth: usize,         // Maximum directory depth below the root. 0 = no limit
+    pub same_file_system: bool,   // Do not cross file system boundaries (mount points)
+    pub binary_same_type: bool,   // BINARY: only files of the same detected type are duplicates
+    pub image_distance: u32,      // IMAGE_SIMILAR: max. hamming distance of similar images (0..64)
+    pub acoustic_threshold: u32,  // ACOUSTIC: min. fingerprint similarity in percent
+    pub acoustic_seconds: u32,    // ACOUSTIC: seconds of audio used for the fingerprint
+    pub match_profile: MatchProfile, // METADATA: fields which form the match key
+    pub duration_tolerance: f32,  // METADATA: max. difference of the durations in seconds
+    pub fuzzy_threshold: u32,     // METADATA: min. similarity of text fields in percent. 100 = exact
+    pub musicbrainz_match: bool,  // METADATA: match files with a MusicBrainz recording ID by the ID
+    pub musicbrainz_release: bool, // METADATA: the release and track IDs must match too
+    pub cross_album: bool,        // METADATA: copies of a track on other albums are duplicates
+    pub normalize_rules: NormalizeRules, // METADATA: rules of the 'normalize' fields
+    pub integrity_check: bool,    // Decode the audio files of each group to find broken copies
+}
+
+impl ScanOptions {
+    pub fn new() -> Self {
+        Self {
+            include_patterns: String::new(),
+            exclude_patterns: String::new(),
+            use_gitignore: false,
+            use_dffignore: true,
+            min_size: 0,
+            max_size: 0,
+            follow_links: false,
+            skip_hidden: false,
+            max_depth: 0,
+            same_file_system: false,
+            binary_same_type: false,
+            image_distance: 6,
+            acoustic_threshold: 60,
+            acoustic_seconds: 120,
+            match_profile: MatchProfile::full(),
+            duration_tolerance: 1.0,
+            fuzzy_threshold: 100,
+            musicbrainz_match: true,
+            musicbrainz_release: false,
+            cross_album: true,
+            normalize_rules: NormalizeRules::new(),
+            integrity_check: false,
+        }
+    }
+
+    /// Returns true if a file with the given length is within the size limits
+    pub fn is_size_selected(&self, length: u64) -> bool {
+        length >= self.min_size && (self.max_size == 0 || length <= self.max_size)
+    }
+
+    /// Derive the options used by a scan. With cross album matches the album fields are removed
+    /// from the match profile, so copies on other albums are duplicates. Otherwise only copies
+    /// from the same release are duplicates, so the release fields are added. The
+    /// normalization rules are compiled.
+    pub fn prepare(&mut self) -> Result<(), regex::Error> {
+        self.match_profile = if self.cross_album {
+            self.match_profile.clone().without_album_fields()
+        } else {
+            self.match_profile.clone().with_release_fields()
+        };
+        self.normalize_rules.compile()
+    }
+
+    /// Returns true if text fields of the METADATA scan are compared by similarity
+    pub fn is_fuzzy(&self) -> bool {
+        self.fuzzy_threshold < 100
+    }
+
+    /// Returns the key of the METADATA bucket of a file.
+    ///
+    /// All files are bucketed by the match profile, so tagged and untagged copies can match.
+    /// Files the profile can't key are bucketed by their MusicBrainz ID, if they have one.
+    pub fn metadata_bucket_key(&self, tags: &HashMap<String, String>) -> Result<String, Error> {
+        let key = self.match_profile.bucket_key(tags, self.is_fuzzy(), &self.normalize_rules);
+        match (key, self.musicbrainz_key(tags)) {
+            (Err(_), Some(id)) => Ok(format!("MBID:{}", id)),
+            (key, _) => key,
+        }
+    }
+
+    /// Returns the MusicBrainz ID of a file if matching by the ID is enabled
+    pub fn musicbrainz_key(&self, tags: &HashMap<String, String>) -> Option<String> {
+        if self.musicbrainz_match {
+            musicbrainz_key(tags, self.musicbrainz_release)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the names of the ignore files to honour
+    pub fn ignore_files(&self) -> Vec<&'static str> {
+        let mut files = Vec::new();
+        if self.use_gitignore {
+            files.push(".gitignore");
+        }
+        if self.use_dffignore {
+            files.push(".dffignore");
+        }
+        files
+    }
+}
@@ -0,0 +1,463 @@
+use crate::components::basic::string_utils::NormalizeRules;
+
+use anyhow::{anyhow, Error};
+use strsim::jaro_winkler;
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+use std::{
+    collections::HashMap,
+    fs,
+    io,
+    path::Path,
+};
+
+const PROFILES_NAME: &str = "./match_profiles.txt";
+
+/// Album artists of compilations, compared lowercase
+const VARIOUS_ARTISTS: [&str; 6] = ["various artists", "various", "va", "v.a.", "verschiedene interpreten", "diverse"];
+
+/// Leading articles which are ignored for the fuzzy bucket, compared lowercase
+const ARTICLES: [&str; 11] = ["the ", "a ", "an ", "die ", "der ", "das ", "le ", "la ", "les ", "el ", "los "];
+
+/// Keys of the album title, removed from the profile for cross album matches
+const ALBUM_KEYS: [&str; 2] = ["AlbumTitle", "OriginalAlbumTitle"];
+
+/// Tag and property keys offered in the profile editor. Any other key of the tag map can be
+/// entered by hand.
+pub const MATCH_KEYS: [&str; 24] = [
+    "TrackTitle",
+    "TrackArtist",
+    "TrackArtists",
+    "AlbumTitle",
+    "AlbumArtist",
+    "OriginalAlbumTitle",
+    "OriginalArtist",
+    "Composer",
+    "Genre",
+    "Year",
+    "RecordingDate",
+    "TrackNumber",
+    "DiscNumber",
+    "Isrc",
+    "Label",
+    "CatalogNumber",
+    "MusicBrainzRecordingId",
+    "MusicBrainzReleaseId",
+    "Duration",
+    "SampleRate",
+    "Channels",
+    "AudioBitrate",
+    "OverallBitrate",
+    "BitDepth",
+];
+
+/// How the value of a field is normalized before it is compared
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Normalization {
+    NONE,      // Compare the value as it is
+    LOWERCASE, // Ignore the case
+    NORMALIZE, // By the normalization rules of the settings
+    NUMBER,    // Track or disc number without leading zeros and total, i.e. '03/12' -> '3'
+}
+
+impl Normalization {
+    pub const ALL: [Normalization; 4] =
+        [Normalization::NONE, Normalization::LOWERCASE, Normalization::NORMALIZE, Normalization::NUMBER];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Normalization::NONE => "exact",
+            Normalization::LOWERCASE => "lowercase",
+            Normalization::NORMALIZE => "normalize",
+            Normalization::NUMBER => "number",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|n| n.name() == name)
+    }
+
+    pub fn apply(&self, value: &str, rules: &NormalizeRules) -> String {
+        match self {
+            Normalization::NONE => value.trim().to_string(),
+            Normalization::LOWERCASE => value.trim().to_lowercase(),
+            Normalization::NORMALIZE => rules.normalize(value),
+            Normalization::NUMBER => {
+                let digits: String = value.trim().chars().take_while(|c| c.is_ascii_digit()).collect();
+                digits.parse::<u32>().map(|n| n.to_string()).unwrap_or_default()
+            }
+        }
+    }
+}
+
+/// One part of the match key.
+///
+/// 'keys' holds alternatives separated by '|', i.e. 'AlbumArtist|TrackArtist'. The first
+/// key with a non empty value is used.
+#[derive(Debug, PartialEq, Clone)]
+pub struct MatchField {
+    pub keys: String,
+    pub normalization: Normalization,
+    pub required: bool, // Files without a value are not compared
+}
+
+impl MatchField {
+    pub fn new(keys: &str, normalization: Normalization, required: bool) -> Self {
+        Self { keys: keys.to_string(), normalization, required }
+    }
+
+    /// The duration is compared with a tolerance instead of by its value
+    pub fn is_duration(&self) -> bool {
+        self.keys.trim() == "Duration"
+    }
+
+    /// Returns the normalized value of the first alternative found in 'tags'.
+    ///
+    /// The album artist of a compilation (i.e. 'Various Artists') is skipped, so the next
+    /// alternative (usually the track artist) is used.
+    pub fn value(&self, tags: &HashMap<String, String>, rules: &NormalizeRules) -> Option<String> {
+        self.keys
+            .split('|')
+            .map(|key| key.trim())
+            .filter(|key| !(*key == "AlbumArtist" && is_compilation(tags)))
+            .filter_map(|key| tags.get(key))
+            .map(|value| self.normalization.apply(value, rules))
+            .find(|value| !value.is_empty())
+    }
+
+    /// Numbers are always compared exactly, also by a fuzzy scan
+    fn is_exact(&self) -> bool {
+        self.normalization == Normalization::NUMBER
+    }
+}
+
+/// A named set of fields which form the key of the METADATA scan.
+///
+/// Files with equal keys are duplicates.
+#[derive(Debug, PartialEq, Clone)]
+pub struct MatchProfile {
+    pub name: String,
+    pub fields: Vec<MatchField>,
+}
+
+impl MatchProfile {
+    pub fn new(name: &str) -> Self {
+        Self { name: name.to_string(), fields: Vec::new() }
+    }
+
+    /// Duration, artist, album and title
+    pub fn full() -> Self {
+        Self {
+            name: "Artist, album and title".to_string(),
+            fields: vec![
+                MatchField::new("Duration", Normalization::NONE, false),
+                MatchField::new("AlbumArtist|TrackArtist", Normalization::NONE, false),
+                MatchField::new("AlbumTitle|OriginalAlbumTitle", Normalization::NONE, false),
+                MatchField::new("TrackTitle", Normalization::NORMALIZE, false),
+            ],
+        }
+    }
+
+    /// Duration and title only
+    pub fn short() -> Self {
+        Self {
+            name: "Title".to_string(),
+            fields: vec![
+                MatchField::new("Duration", Normalization::NONE, true),
+                MatchField::new("TrackTitle", Normalization::NORMALIZE, true),
+            ],
+        }
+    }
+
+    /// Add the album, disc and track number, so only copies of the same track of the same
+    /// release match. Fields already in the profile are replaced.
+    pub fn with_release_fields(mut self) -> Self {
+        let release = [
+            MatchField::new("AlbumTitle|OriginalAlbumTitle", Normalization::NORMALIZE, false),
+            MatchField::new("DiscNumber", Normalization::NUMBER, false),
+            MatchField::new("TrackNumber", Normalization::NUMBER, false),
+        ];
+        for field in release {
+            match self.fields.iter_mut().find(|f| f.keys == field.keys) {
+                Some(existing) => *existing = field,
+                None => self.fields.push(field),
+            }
+        }
+        self
+    }
+
+    /// Remove the album fields, so copies of a track on other albums (i.e. a best-of) match
+    pub fn without_album_fields(mut self) -> Self {
+        let is_album = |field: &MatchField| field.keys.split('|').any(|key| ALBUM_KEYS.contains(&key.trim()));
+        self.fields.retain(|field| !is_album(field));
+        self
+    }
+
+    /// Returns the normalized values of the fields of a file.
+    ///
+    /// Returns an error if a required field or all fields have no value. The duration of
+    /// the audio stream doesn't count if the profile has other fields, so untagged files don't
+    /// match by their duration alone.
+    pub fn values(&self, tags: &HashMap<String, String>, rules: &NormalizeRules) -> Result<Vec<String>, Error> {
+        let mut values = Vec::with_capacity(self.fields.len());
+        for field in self.fields.iter() {
+            match field.value(tags, rules) {
+                Some(value) => values.push(value),
+                None if field.required => return Err(anyhow!("{} is empty", field.keys)),
+                None => values.push(String::new()),
+            }
+        }
+        let only_duration = self.fields.iter().all(|f| f.is_duration());
+        let tagged = self.fields.iter().zip(values.iter()).any(|(f, v)| !v.is_empty() && (only_duration || !f.is_duration()));
+        if !tagged {
+            return Err(anyhow!("No value for the fields of profile '{}'", self.name));
+        }
+        Ok(values)
+    }
+
+    /// Build the match key from the tags of a file
+    pub fn key(&self, tags: &HashMap<String, String>, rules: &NormalizeRules) -> Result<String, Error> {
+        Ok(self.values(tags, rules)?.join("|"))
+    }
+
+    pub fn has_duration(&self) -> bool {
+        self.fields.iter().any(|f| f.is_duration())
+    }
+
+    /// Key of the bucket of a file in the METADATA scan. Only files in the same bucket are
+    /// compared.
+    ///
+    /// The duration is never part of the bucket key, it is compared with a tolerance. With
+    /// 'fuzzy' text comparison the numbers and the initial of the first text value are used,
+    /// see 'bucket_initial'. Otherwise all values are used.
+    pub fn bucket_key(&self, tags: &HashMap<String, String>, fuzzy: bool, rules: &NormalizeRules) -> Result<String, Error> {
+        let values = self.values(tags, rules)?;
+        let fields = || self.fields.iter().zip(values.iter()).filter(|(f, _)| !f.is_duration());
+        if fuzzy {
+            let first = fields().filter(|(f, _)| !f.is_exact()).map(|(_, v)| bucket_initial(v)).find(|c| *c != ' ').unwrap_or(' ');
+            let mut key: Vec<String> = fields().filter(|(f, _)| f.is_exact()).map(|(_, v)| v.clone()).collect();
+            key.push(first.to_string());
+            Ok(key.join("|"))
+        } else {
+            Ok(fields().map(|(_, v)| v.as_str()).collect::<Vec<&str>>().join("|"))
+        }
+    }
+
+    /// Compare the values of two files.
+    ///
+    /// The durations (in milliseconds) may differ by 'tolerance_ms', text values need a
+    /// Jaro-Winkler similarity of at least 'threshold' (0.0..=1.0).
+    pub fn is_match(&self, a: (&[String], Option<u64>), b: (&[String], Option<u64>), tolerance_ms: u64, threshold: f64) -> bool {
+        for (i, field) in self.fields.iter().enumerate() {
+            let (value_a, value_b) = (&a.0[i], &b.0[i]);
+            if field.is_duration() {
+                let matches = match (a.1, b.1) {
+                    (Some(ms_a), Some(ms_b)) if tolerance_ms > 0 => ms_a.abs_diff(ms_b) <= tolerance_ms,
+                    _ => value_a == value_b,
+                };
+                if !matches {
+                    return false;
+                }
+            } else if value_a != value_b
+                && (field.is_exact()
+                    || value_a.is_empty()
+                    || value_b.is_empty()
+                    || jaro_winkler(value_a, value_b) < threshold)
+            {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// First letter of a text value for the fuzzy bucket. Case, diacritics, punctuation and a
+/// leading article are ignored, so 'The Beatles' and 'beatles' or 'Öl' and 'Ol' share a bucket
+/// also if the field is not normalized.
+fn bucket_initial(value: &str) -> char {
+    let folded = value.nfkd().filter(|c| !is_combining_mark(*c)).collect::<String>().to_lowercase();
+    let text = folded.trim_start_matches(|c: char| !c.is_alphanumeric());
+    let text = ARTICLES.iter().find_map(|article| text.strip_prefix(article)).unwrap_or(text);
+    text.chars().find(|c| c.is_alphanumeric()).unwrap_or(' ')
+}
+
+/// Returns true if the file is part of a compilation, by the compilation flag or the album artist
+pub fn is_compilation(tags: &HashMap<String, String>) -> bool {
+    let flag = tags.get("FlagCompilation").map(|f| f.trim().to_lowercase());
+    if matches!(flag.as_deref(), Some("1") | Some("true") | Some("yes")) {
+        return true;
+    }
+    tags.get("AlbumArtist").is_some_and(|artist| VARIOUS_ARTISTS.contains(&artist.trim().to_lowercase().as_str()))
+}
+
+/// Returns the MusicBrainz recording ID of a file, with 'with_release' combined with the
+/// release and track IDs. Returns None if one of the IDs is missing.
+pub fn musicbrainz_key(tags: &HashMap<String, String>, with_release: bool) -> Option<String> {
+    let id = |key: &str| tags.get(key).map(|id| id.trim().to_lowercase()).filter(|id| !id.is_empty());
+
+    let recording = id("MusicBrainzRecordingId")?;
+    if with_release {
+        Some(format!("{}:{}:{}", recording, id("MusicBrainzReleaseId")?, id("MusicBrainzTrackId")?))
+    } else {
+        Some(recording)
+    }
+}
+
+/// Returns the exact duration of a file in milliseconds
+pub fn duration_ms(tags: &HashMap<String, String>) -> Option<u64> {
+    tags.get("DurationMs").and_then(|ms| ms.parse().ok())
+}
+
+/// Load the profiles from the profiles file. Returns the default profiles if there is no file.
+///
+/// The file holds a '[name]' line per profile followed by one line per field in the form
+/// 'keys;normalization[;required]'.
+pub fn load_profiles() -> Vec<MatchProfile> {
+    match fs::read_to_string(PROFILES_NAME) {
+        Ok(content) => {
+            let profiles = parse_profiles(&content);
+            if profiles.is_empty() {
+                default_profiles()
+            } else {
+                profiles
+            }
+        }
+        Err(_) => default_profiles(),
+    }
+}
+
+/// Write the profiles to the profiles file
+pub fn save_profiles(profiles: &[MatchProfile]) -> io::Result<()> {
+    let mut content = String::new();
+    for profile in profiles.iter() {
+        content.push_str(&format!("[{}]\n", profile.name));
+        for field in profile.fields.iter() {
+            content.push_str(&format!("{};{}", field.keys, field.normalization.name()));
+            if field.required {
+                content.push_str(";required");
+            }
+            content.push('\n');
+        }
+        content.push('\n');
+    }
+    fs::write(Path::new(PROFILES_NAME), content)
+}
+
+pub fn default_profiles() -> Vec<MatchProfile> {
+    vec![MatchProfile::full(), MatchProfile::short()]
+}
+
+fn parse_profiles(content: &str) -> Vec<MatchProfile> {
+    let mut profiles: Vec<MatchProfile> = Vec::new();
+    for line in content.lines().map(|l| l.trim()).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+        if line.starts_with('[') && line.ends_with(']') {
+            profiles.push(MatchProfile::new(&line[1..line.len() - 1]));
+            continue;
+        }
+        let profile = match profiles.last_mut() {
+            Some(profile) => profile,
+            None => continue, // Field without a profile
+        };
+        let mut parts = line.split(';').map(|p| p.trim());
+        let keys = parts.next().unwrap_or_default();
+        let normalization = parts.next().and_then(Normalization::from_name).unwrap_or(Normalization::NONE);
+        let required = parts.next() == Some("required");
+        profile.fields.push(MatchField::new(keys, normalization, required));
+    }
+    profiles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cross_album_profile_has_no_album_field() {
+        let profile = MatchProfile::full().without_album_fields();
+        assert!(!profile.fields.iter().any(|f| f.keys.contains("AlbumTitle")));
+        assert_eq!(profile.fields.len(), MatchProfile::full().fields.len() - 1);
+    }
+
+    #[test]
+    fn release_fields_replace_the_album_field() {
+        let profile = MatchProfile::full().with_release_fields();
+        let albums: Vec<&MatchField> = profile.fields.iter().filter(|f| f.keys == "AlbumTitle|OriginalAlbumTitle").collect();
+        assert_eq!(albums.len(), 1);
+        assert_eq!(albums[0].normalization, Normalization::NORMALIZE);
+        assert!(profile.fields.iter().any(|f| f.keys == "TrackNumber"));
+        assert!(profile.fields.iter().any(|f| f.keys == "DiscNumber"));
+    }
+
+    fn tags(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    fn rules() -> NormalizeRules {
+        let mut rules = NormalizeRules::new();
+        rules.compile().unwrap();
+        rules
+    }
+
+    #[test]
+    fn fuzzy_bucket_ignores_case_diacritics_and_articles() {
+        let profile = MatchProfile::full();
+        let a = tags(&[("AlbumArtist", "The Beatles"), ("TrackTitle", "Help")]);
+        let b = tags(&[("AlbumArtist", "beatles"), ("TrackTitle", "Help!")]);
+        let c = tags(&[("AlbumArtist", "Öl"), ("TrackTitle", "Help")]);
+        let d = tags(&[("AlbumArtist", "Ol"), ("TrackTitle", "Help")]);
+        let key = |t| profile.bucket_key(t, true, &rules()).unwrap();
+        assert_eq!(key(&a), key(&b));
+        assert_eq!(key(&c), key(&d));
+        assert_ne!(key(&a), key(&c));
+    }
+
+    #[test]
+    fn fuzzy_bucket_keeps_numbers_and_drops_the_duration() {
+        let profile = MatchProfile::short().with_release_fields();
+        let a = tags(&[("Duration", "180000"), ("TrackTitle", "Song"), ("TrackNumber", "03/12")]);
+        let b = tags(&[("Duration", "181000"), ("TrackTitle", "Song"), ("TrackNumber", "3")]);
+        let c = tags(&[("Duration", "180000"), ("TrackTitle", "Song"), ("TrackNumber", "4")]);
+        let key = |t| profile.bucket_key(t, true, &rules()).unwrap();
+        assert_eq!(key(&a), key(&b));
+        assert_ne!(key(&a), key(&c));
+        assert!(!profile.bucket_key(&a, false, &rules()).unwrap().contains("180000"));
+    }
+
+    #[test]
+    fn bucket_of_untagged_file_is_an_error() {
+        let untagged = tags(&[("Duration", "180000")]);
+        assert!(MatchProfile::full().bucket_key(&untagged, true, &rules()).is_err());
+        assert!(MatchProfile::short().bucket_key(&untagged, true, &rules()).is_err());
+    }
+
+    #[test]
+    fn duration_matches_within_the_tolerance() {
+        let mut profile = MatchProfile::new("Duration");
+        profile.fields.push(MatchField::new("Duration", Normalization::NONE, true));
+        let (a, b) = (vec!["180000".to_string()], vec!["181500".to_string()]);
+        assert!(profile.is_match((&a, Some(180_000)), (&b, Some(181_500)), 2000, 1.0));
+        assert!(!profile.is_match((&a, Some(180_000)), (&b, Some(181_500)), 1000, 1.0));
+        // Without a tolerance the values have to be equal
+        assert!(!profile.is_match((&a, Some(180_000)), (&b, Some(181_500)), 0, 1.0));
+        assert!(profile.is_match((&a, Some(180_000)), (&a, Some(180_000)), 0, 1.0));
+    }
+
+    #[test]
+    fn text_matches_by_the_threshold() {
+        let profile = MatchProfile::new("Title").with_release_fields();
+        let a = vec!["album".to_string(), "1".to_string(), "3".to_string()];
+        let b = vec!["albun".to_string(), "1".to_string(), "3".to_string()];
+        assert!(profile.is_match((&a, None), (&b, None), 0, 0.9));
+        assert!(!profile.is_match((&a, None), (&b, None), 0, 1.0));
+        let empty = vec![String::new(), "1".to_string(), "3".to_string()];
+        assert!(!profile.is_match((&a, None), (&empty, None), 0, 0.0));
+    }
+
+    #[test]
+    fn numbers_match_exactly_also_by_a_fuzzy_threshold() {
+        let profile = MatchProfile::new("Title").with_release_fields();
+        let a = vec!["album".to_string(), "1".to_string(), "12".to_string()];
+        let b = vec!["album".to_string(), "1".to_string(), "13".to_string()];
+        assert!(!profile.is_match((&a, None), (&b, None), 0, 0.0));
+        assert_eq!(Normalization::NUMBER.apply("03/12", &rules()), "3");
+    }
+}
@@ -1,10 +1,20 @@
+use crate::scanner::album::check_albums;
+use crate::scanner::filter::PathFilter;
+use crate::scanner::lint::{lint_albums, lint_file};
 use crate::scanner::mediatype::{MediaGroup, ScanType};
-use crate::scanner::messenger::Messenger;
+use crate::scanner::messenger::{Finding, Messenger};
+use crate::scanner::options::ScanOptions;
+use crate::scanner::profile::duration_ms;
+use crate::scanner::tree::{find_duplicate_dirs, TreeFile};
+use crate::components::basic::audio_utils::*;
 use crate::components::basic::file_utils::*;
+use crate::components::basic::image_utils::*;
 use crate::components::basic::lofty_utils::*;
+use crate::components::basic::magic_utils::detect_file_kind;
+use crate::components::basic::payload_utils::*;
 
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     fs::File,
     io::{Write},
     path::Path,
@@ -13,23 +23,69 @@ use walkdir::{DirEntry, WalkDir};
 
 const SCRIPT_NAME: &str = "./duplicates.log";
 
-pub fn scan(path: &Path, scan_type: ScanType, media_groups: Vec<MediaGroup>, messenger: Messenger) {
+/// Scan the given 'path' for duplicates.
+///
+/// If a 'reference' directory is given, only files from 'path' which have a copy in the
+/// reference are reported. Files inside the reference are never reported.
+pub fn scan(
+    path: &Path,
+    reference: Option<&Path>,
+    scan_type: ScanType,
+    media_groups: Vec<MediaGroup>,
+    options: ScanOptions,
+    messenger: Messenger,
+) {
     if !path.is_dir() {
         messenger.push_errlog(format!("{:?} must be a directory", path));
         return;
     }
 
-    // 1. Walk recursive down from the root_path and group files by size/type
-    let mut metas = walk_dir(&path, &scan_type, &media_groups, &messenger);
+    // Roots to walk. The bool marks the reference root
+    let mut roots: Vec<(&Path, bool)> = vec![(path, false)];
+    if let Some(reference) = reference {
+        if !reference.is_dir() {
+            messenger.push_errlog(format!("Reference {:?} must be a directory", reference));
+            return;
+        }
+        if reference == path {
+            messenger.push_errlog(format!("Reference {:?} must differ from the scanned path", reference));
+            return;
+        }
+        roots.push((reference, true));
+    }
+
+    let mut options = options;
+    if let Err(e) = options.prepare() {
+        messenger.push_errlog(format!("Invalid normalization rule: {}", e));
+        return;
+    }
+
+    // 1. Walk recursive down from the root_paths and group files by size/type
+    let mut metas = walk_dir(&roots, &scan_type, &media_groups, &options, &messenger);
 
     // 2. Calculate file checksum from the first BUF_SIZE bytes from
     match scan_type {
-        ScanType::BINARY => calc_checksum(&mut metas, &messenger),
+        ScanType::BINARY | ScanType::DIRECTORY => calc_checksum(&mut metas, &messenger),
         _ => (),
     };
 
     // 3. Compare complete files if size/type and checksum are equal and build duplicates list
-    let duplicates = check_for_duplicates(&scan_type, &metas, &messenger);
+    let duplicates = match scan_type {
+        ScanType::DIRECTORY => {
+            let root_paths: Vec<&Path> = roots.iter().map(|(root, _)| *root).collect();
+            check_for_duplicate_dirs(&metas, &root_paths, reference.is_some(), &messenger)
+        }
+        ScanType::IMAGE_SIMILAR => check_for_similar_images(&metas, options.image_distance, reference.is_some(), &messenger),
+        ScanType::ACOUSTIC => check_for_acoustic_duplicates(&metas, &options, reference.is_some(), &messenger),
+        ScanType::LINT => {
+            let files = metas.values().flatten().map(|fi| (fi.path(), &fi.tags));
+            lint_albums(files).into_iter().for_each(|finding| messenger.push_report(finding));
+            let files = metas.values().flatten().map(|fi| (fi.path(), &fi.tags));
+            check_albums(files).into_iter().for_each(|finding| messenger.push_report(finding));
+            Vec::new()
+        }
+        _ => check_for_duplicates(&scan_type, &metas, &options, reference.is_some(), &messenger),
+    };
 
     // 4. Print the duplicates to stdout
     match create_bash_script(&duplicates) {
@@ -44,24 +100,87 @@ pub fn scan(path: &Path, scan_type: ScanType, media_groups: Vec<MediaGroup>, mes
     }
 }
 
-/// Scan recursively the file system from the given 'root_path'.
+/// Scan recursively the file system from the given 'roots'.
 ///
 /// This creates a HashMap that has a key consisting of length:extension, and holds a list
-/// of DirEntry entries for each file.
+/// of DirEntry entries for each file. A root nested inside another root is only walked once,
+/// as part of its own root. Entries excluded by the patterns or ignore files are skipped
+/// before they are stat'ed.
 fn walk_dir(
-    root_path: &Path,
+    roots: &[(&Path, bool)],
     scan_type: &ScanType,
     media_groups: &Vec<MediaGroup>,
+    options: &ScanOptions,
     messenger: &Messenger,
 ) -> HashMap<String, Vec<FileInfo>> {
     let mut fileinfo_map: HashMap<String, Vec<FileInfo>> = HashMap::new();
+    let reference_mode = roots.iter().any(|(_, reference)| *reference);
+
+    let mut filter = match PathFilter::new(options) {
+        Ok(filter) => filter,
+        Err(e) => {
+            messenger.push_errlog(format!("Invalid pattern: {}", e));
+            return fileinfo_map;
+        }
+    };
 
     messenger.set_info("Scanning...".to_owned());
 
-    for entry in WalkDir::new(root_path)
-        .into_iter()
-        .filter_map(Result::ok)
-        .filter(|e| !e.path().is_dir())
+    for (root_path, reference) in roots.iter() {
+        let mut walkdir = WalkDir::new(root_path)
+            .follow_links(options.follow_links)
+            .same_file_system(options.same_file_system);
+        if options.max_depth > 0 {
+            walkdir = walkdir.max_depth(options.max_depth);
+        }
+
+        let walker = walkdir
+            .into_iter()
+            .filter_entry(|e| {
+                !roots.iter().any(|(other, _)| other != root_path && e.path() == *other)
+                    && !filter.is_excluded(e, root_path)
+            });
+        walk_root(walker, *reference, scan_type, media_groups, options, messenger, &mut fileinfo_map);
+    }
+
+    // All files are needed for the directory digests and the album checks
+    if *scan_type == ScanType::DIRECTORY || *scan_type == ScanType::LINT {
+        return fileinfo_map;
+    }
+
+    if *scan_type == ScanType::METADATA {
+        merge_musicbrainz_buckets(&mut fileinfo_map, options);
+        report_differing_covers(&fileinfo_map, messenger);
+        let files = fileinfo_map.values().flatten().filter(|fi| !fi.reference).map(|fi| (fi.path(), &fi.tags));
+        check_albums(files).into_iter().for_each(|finding| messenger.push_report(finding));
+    }
+
+    // Retain only duplicate elements. With a reference, a copy must exist on both sides
+    fileinfo_map.retain(|_, v| {
+        v.len() > 1 && (!reference_mode || (v.iter().any(|fi| fi.reference) && v.iter().any(|fi| !fi.reference)))
+    });
+    fileinfo_map
+}
+
+fn walk_root(
+    walker: impl Iterator<Item = walkdir::Result<DirEntry>>,
+    reference: bool,
+    scan_type: &ScanType,
+    media_groups: &[MediaGroup],
+    options: &ScanOptions,
+    messenger: &Messenger,
+    fileinfo_map: &mut HashMap<String, Vec<FileInfo>>,
+) {
+    for entry in walker
+        .filter_map(|e| match e {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                // i.e. symlink loops or missing permissions
+                messenger.push_errlog(format!("{}", e));
+                None
+            }
+        })
+        .filter(|e| e.file_type().is_file())
     {
         if messenger.is_stopped() || messenger.is_interrupted() {
             break;
@@ -73,8 +192,37 @@ fn walk_dir(
         }
 
         let metadata = entry.metadata().ok().unwrap();
-        let file_info = FileInfo::new(entry.clone());
-        let extension = get_extension(file_info.path_to_str());
+        let mut file_info = FileInfo::new(entry.clone(), reference);
+
+        // Zero-byte files are reported after the type filter
+        if metadata.len() > 0 && !options.is_size_selected(metadata.len()) {
+            continue;
+        }
+
+        // Classify by content. The extension is only used if the content is not recognized
+        let mut extension = file_info.extension.clone();
+        match detect_file_kind(file_info.path()) {
+            Ok(Some(kind)) if kind.is_ignored() => {
+                messenger.push_errlog(format!("{} file ignored: {}", kind.name, file_info.path_to_str()));
+                continue;
+            }
+            Ok(Some(kind)) if !kind.has_extension(&extension) => {
+                let message = if extension.is_empty() {
+                    format!("No extension but content is {}", kind.name)
+                } else {
+                    format!("Extension {} but content is {}", extension, kind.name)
+                };
+                messenger.push_report(Finding::new("Mismatch", file_info.path_to_str(), &message));
+                extension = kind.canonical_extension();
+                file_info.file_type = kind.name.to_string();
+            }
+            Ok(Some(kind)) => file_info.file_type = kind.name.to_string(),
+            Ok(None) => (),
+            Err(e) => {
+                messenger.push_errlog(format!("Could not read {} : {}", file_info.path_to_str(), e));
+                continue;
+            }
+        }
 
         // If unknown extension then log in error
         if !media_groups.iter().any(|mg| (mg.is_known_extension(&extension) && mg.is_selected(&extension))) {
@@ -82,20 +230,79 @@ fn walk_dir(
             continue;
         }
 
+        // Zero-byte files would all match each other. Report them separately
+        if metadata.len() == 0 {
+            if !reference {
+                messenger.push_report(Finding::new("Empty", file_info.path_to_str(), "Zero-byte file"));
+            }
+            continue;
+        }
+
         let key: String;
         match scan_type {
             ScanType::BINARY => {
-                key = file_info.get_key(metadata.len());
+                key = file_info.get_key(metadata.len(), options.binary_same_type);
             } // binary
+            ScanType::DIRECTORY => {
+                key = file_info.get_key(metadata.len(), false);
+            } // directory
+            ScanType::IMAGE_SIMILAR => {
+                // All images are compared with each other
+                if !media_groups.iter().any(|mg| mg.name == "Image" && mg.is_known_extension(&extension)) {
+                    continue;
+                }
+                key = "IMAGE".to_string();
+            } // image_similar
+            ScanType::ACOUSTIC => {
+                // All audio files are compared by their fingerprints
+                if !media_groups.iter().any(|mg| mg.name == "Audio" && mg.is_known_extension(&extension)) {
+                    continue;
+                }
+                key = "AUDIO".to_string();
+            } // acoustic
+            ScanType::AUDIO_PAYLOAD => {
+                // Audio files with the same length of the audio stream
+                if !media_groups.iter().any(|mg| mg.name == "Audio" && mg.is_known_extension(&extension)) {
+                    continue;
+                }
+                key = match get_audio_payload(file_info.path()) {
+                    Ok(ranges) => format!("{}", payload_length(&ranges)),
+                    Err(e) => {
+                        messenger.push_errlog(format!("Could not parse audio {} : {}", file_info.path_to_str(), e));
+                        continue;
+                    }
+                }
+            } // audio_payload
             ScanType::METADATA => {
-                key = match get_short_audio_key(&file_info.path()) {
-                    Ok(key) => key,
+                let tags = get_audio_tags(file_info.path());
+                match tags.and_then(|tags| Ok((options.metadata_bucket_key(&tags)?, tags))) {
+                    Ok((bucket, tags)) => {
+                        key = bucket;
+                        file_info.tags = tags;
+                    }
                     Err(e) => {
                         messenger.push_errlog(format!("{:?} : file: {:?}", e.to_string(), file_info.path()));
                         continue;
                     }
                 }
             } // metadata
+            ScanType::LINT => {
+                // The tags of the audio files are checked, reference files are skipped
+                if reference || !media_groups.iter().any(|mg| mg.name == "Audio" && mg.is_known_extension(&extension)) {
+                    continue;
+                }
+                match get_audio_tags(file_info.path()) {
+                    Ok(tags) => {
+                        lint_file(file_info.path(), &tags, options).into_iter().for_each(|finding| messenger.push_report(finding));
+                        file_info.tags = tags;
+                    }
+                    Err(e) => {
+                        messenger.push_errlog(format!("{:?} : file: {:?}", e.to_string(), file_info.path()));
+                        continue;
+                    }
+                }
+                key = "LINT".to_string();
+            } // lint
         } // match ScanType
 
         // Add key to list
@@ -103,10 +310,61 @@ fn walk_dir(
         let entries = fileinfo_map.entry(key).or_insert(Vec::new());
         entries.push(file_info);
     }
+}
 
-    // Retain only duplicate elements
-    fileinfo_map.retain(|_, v| v.len() > 1);
-    fileinfo_map
+/// Merge the METADATA buckets whose files share a MusicBrainz ID, so the files with the same
+/// ID are compared even if the match profile put them in different buckets.
+fn merge_musicbrainz_buckets(fileinfo_map: &mut HashMap<String, Vec<FileInfo>>, options: &ScanOptions) {
+    if !options.musicbrainz_match {
+        return;
+    }
+    let keys: Vec<String> = fileinfo_map.keys().cloned().collect();
+    let mut first_bucket: HashMap<String, usize> = HashMap::new();
+    let mut pairs: Vec<(usize, usize)> = Vec::new();
+    for (bucket, key) in keys.iter().enumerate() {
+        for file_info in fileinfo_map[key].iter() {
+            if let Some(id) = options.musicbrainz_key(&file_info.tags) {
+                let first = *first_bucket.entry(id).or_insert(bucket);
+                if first != bucket {
+                    pairs.push((first, bucket));
+                }
+            }
+        }
+    }
+
+    for buckets in cluster(keys.len(), &pairs) {
+        let mut merged: Vec<FileInfo> = Vec::new();
+        for bucket in buckets.iter() {
+            merged.extend(fileinfo_map.remove(&keys[*bucket]).unwrap_or_default());
+        }
+        fileinfo_map.insert(keys[buckets[0]].clone(), merged);
+    }
+}
+
+/// Report albums whose tracks carry different embedded covers, or where only some tracks
+/// have a cover. Tracks are grouped by album artist (or artist) and album title.
+fn report_differing_covers(fileinfo_map: &HashMap<String, Vec<FileInfo>>, messenger: &Messenger) {
+    let mut albums: HashMap<(&str, &str), Vec<&FileInfo>> = HashMap::new();
+    for file_info in fileinfo_map.values().flatten().filter(|fi| !fi.reference) {
+        let tags = &file_info.tags;
+        let artist = tags.get("AlbumArtist").or_else(|| tags.get("TrackArtist"));
+        if let (Some(artist), Some(album)) = (artist, tags.get("AlbumTitle")) {
+            albums.entry((artist.as_str(), album.as_str())).or_default().push(file_info);
+        }
+    }
+
+    for ((artist, album), tracks) in albums.iter() {
+        let covers: HashSet<&str> = tracks.iter().filter_map(|fi| fi.tags.get("CoverHash").map(|h| h.as_str())).collect();
+        let without = tracks.iter().filter(|fi| !fi.tags.contains_key("CoverHash")).count();
+        if covers.len() > 1 || (!covers.is_empty() && without > 0) {
+            let mut message = format!("'{}' by {}: {} different covers in {} tracks", album, artist, covers.len(), tracks.len());
+            if without > 0 {
+                message.push_str(&format!(", {} without cover", without));
+            }
+            let folder = tracks[0].path().parent().unwrap_or(Path::new(""));
+            messenger.push_report(Finding::new("Cover", folder.to_str().unwrap_or(""), &message));
+        }
+    }
 }
 
 /// Calculate a checksums for all files found with have the same size
@@ -138,10 +396,13 @@ fn calc_checksum(map: &mut HashMap<String, Vec<FileInfo>>, messenger: &Messenger
 fn check_for_duplicates(
     scan_type: &ScanType,
     metas: &HashMap<String, Vec<FileInfo>>,
+    options: &ScanOptions,
+    reference_mode: bool,
     messenger: &Messenger,
 ) -> Vec<HashMap<String, String>> {
     let mut count = 0;
     let len = metas.len();
+    let mut group_id = 0;
     let mut duplicates: Vec<HashMap<String, String>> = Vec::new();
 
     for (key, file_infos) in metas.iter() {
@@ -152,73 +413,490 @@ fn check_for_duplicates(
         count += 1;
         messenger.set_progress(len, count, "Check for duplicates...");
         if !key.is_empty() {
-            for dup in find_duplicates(&scan_type, &file_infos, &messenger) {
-                duplicates.push(dup.clone());
-                messenger.push_reslog(dup);
+            for group in find_duplicates(scan_type, file_infos, options, messenger) {
+                let results = group_results(&group, reference_mode, group_id + 1, options.integrity_check, messenger);
+                if !results.is_empty() {
+                    group_id += 1;
+                }
+                for mut dup in results {
+                    if *scan_type == ScanType::METADATA {
+                        dup.insert("MATCHED_BY".to_string(), matched_by(&group, options));
+                    }
+                    duplicates.push(dup.clone());
+                    messenger.push_reslog(dup);
+                }
             }
         }
     }
     duplicates
 }
 
-fn find_duplicates(scan_type: &ScanType, file_infos: &Vec<FileInfo>, messenger: &Messenger) -> Vec<HashMap<String, String>> {
+/// Calculate a content digest for each file and find the duplicate directories.
+///
+/// Only files sharing their length with other files are read. A file with a unique length
+/// gets a unique digest.
+fn check_for_duplicate_dirs(
+    metas: &HashMap<String, Vec<FileInfo>>,
+    roots: &[&Path],
+    reference_mode: bool,
+    messenger: &Messenger,
+) -> Vec<HashMap<String, String>> {
+    let mut count = 0;
+    let len = metas.len();
+    let mut files: Vec<TreeFile> = Vec::new();
+
+    for (key, file_infos) in metas.iter() {
+        if messenger.is_stopped() {
+            return Vec::new();
+        }
+
+        count += 1;
+        messenger.set_progress(len, count, "Calculate file digests...");
+        for file_info in file_infos.iter() {
+            let digest = if file_infos.len() > 1 {
+                match compute_file_checksum(file_info.path()) {
+                    Ok(checksum) => format!("{}:{}:{}", key, file_info.checksum, checksum),
+                    Err(e) => {
+                        messenger.push_errlog(format!("Error for file {:?} : {:?}", file_info.path(), e.to_string()));
+                        format!("unique:{}", file_info.path_to_str())
+                    }
+                }
+            } else {
+                format!("unique:{}", file_info.path_to_str())
+            };
+            files.push(TreeFile { path: file_info.path(), digest, reference: file_info.reference });
+        }
+    }
+
+    let duplicates = find_duplicate_dirs(&files, roots, reference_mode, messenger);
+    for dup in duplicates.iter() {
+        messenger.push_reslog(dup.clone());
+    }
+    duplicates
+}
+
+/// Calculate the perceptual hash of all images and group the images whose hashes differ in
+/// at most 'max_distance' bits. Each member gets its distance to the first member (or the
+/// reference) of the group as similarity score.
+fn check_for_similar_images(
+    metas: &HashMap<String, Vec<FileInfo>>,
+    max_distance: u32,
+    reference_mode: bool,
+    messenger: &Messenger,
+) -> Vec<HashMap<String, String>> {
+    let file_infos: Vec<&FileInfo> = metas.values().flatten().collect();
+    let mut hashes: Vec<(&FileInfo, u64)> = Vec::new();
+
+    for (count, file_info) in file_infos.iter().enumerate() {
+        if messenger.is_stopped() {
+            return Vec::new();
+        }
+        messenger.set_progress(file_infos.len(), count + 1, "Calculate image hashes...");
+        match get_image_hash(file_info.path()) {
+            Ok(hash) => hashes.push((file_info, hash)),
+            Err(e) => messenger.push_errlog(format!("Could not decode image {} : {}", file_info.path_to_str(), e)),
+        }
+    }
+
+    // Each image is looked up in the tree of the images before it
+    let mut pairs: Vec<(usize, usize)> = Vec::new();
+    let mut tree = HashTree::new();
+    for (i, (_, hash)) in hashes.iter().enumerate() {
+        if messenger.is_stopped() {
+            return Vec::new();
+        }
+        messenger.set_progress(hashes.len(), i + 1, "Compare images...");
+        pairs.extend(tree.find(*hash, max_distance).into_iter().map(|j| (j, i)));
+        tree.insert(*hash, i);
+    }
+
+    let mut groups: Vec<Vec<(&FileInfo, u64)>> = cluster(hashes.len(), &pairs)
+        .into_iter()
+        .map(|members| members.iter().map(|i| hashes[*i]).collect())
+        .collect();
+    for group in groups.iter_mut() {
+        group.sort_by(|a, b| a.0.path().cmp(b.0.path()));
+    }
+    groups.sort_by(|a, b| a[0].0.path().cmp(b[0].0.path()));
+
+    let mut group_id = 0;
+    let mut duplicates: Vec<HashMap<String, String>> = Vec::new();
+    for group in groups.iter() {
+        let members: Vec<&FileInfo> = group.iter().map(|(fi, _)| *fi).collect();
+        let results = group_results(&members, reference_mode, group_id + 1, false, messenger);
+        if results.is_empty() {
+            continue;
+        }
+        group_id += 1;
+
+        let first = group.iter().find(|(fi, _)| fi.reference).unwrap_or(&group[0]).1;
+        for mut map in results {
+            let hash = group.iter().find(|(fi, _)| fi.path_to_str() == map["PATH"]).unwrap().1;
+            let distance = hamming_distance(first, hash);
+            map.insert("SCORE".to_string(), similarity(distance).to_string());
+            map.insert("NOTE".to_string(), format!("Distance {} ({}% similar)", distance, similarity(distance)));
+            duplicates.push(map.clone());
+            messenger.push_reslog(map);
+        }
+    }
+    duplicates
+}
+
+/// Calculate the fingerprints of all audio files and group the files whose fingerprints match
+/// with at least 'acoustic_threshold' percent. Each member gets its similarity to the first
+/// member (or the reference) of the group as score.
+fn check_for_acoustic_duplicates(
+    metas: &HashMap<String, Vec<FileInfo>>,
+    options: &ScanOptions,
+    reference_mode: bool,
+    messenger: &Messenger,
+) -> Vec<HashMap<String, String>> {
+    let file_infos: Vec<&FileInfo> = metas.values().flatten().collect();
+    let mut fingerprints: Vec<(&FileInfo, Vec<u32>)> = Vec::new();
+
+    for (count, file_info) in file_infos.iter().enumerate() {
+        if messenger.is_stopped() {
+            return Vec::new();
+        }
+        messenger.set_progress(file_infos.len(), count + 1, "Calculate audio fingerprints...");
+        match get_audio_fingerprint(file_info.path(), options.acoustic_seconds) {
+            Ok(fingerprint) => fingerprints.push((file_info, fingerprint)),
+            Err(e) => messenger.push_errlog(format!("Could not decode audio {} : {}", file_info.path_to_str(), e)),
+        }
+    }
+
+    // Only files sharing some identical sub-fingerprints are compared. Values which occur in
+    // many files (i.e. silence) don't help to find candidates and are skipped.
+    let mut index: HashMap<u32, Vec<usize>> = HashMap::new();
+    for (i, (_, fingerprint)) in fingerprints.iter().enumerate() {
+        let values: HashSet<&u32> = fingerprint.iter().collect();
+        for value in values {
+            index.entry(*value).or_default().push(i);
+        }
+    }
+    let max_postings = (fingerprints.len() / 10).max(50);
+    let mut shared: HashMap<(usize, usize), usize> = HashMap::new();
+    for postings in index.values().filter(|p| p.len() > 1 && p.len() <= max_postings) {
+        for (n, i) in postings.iter().enumerate() {
+            for j in postings[n + 1..].iter() {
+                *shared.entry((*i, *j)).or_default() += 1;
+            }
+        }
+    }
+    let mut candidates: Vec<(usize, usize)> = shared.into_iter().filter(|(_, count)| *count >= 3).map(|(pair, _)| pair).collect();
+    candidates.sort();
+
+    let mut pairs: Vec<(usize, usize)> = Vec::new();
+    for (count, (i, j)) in candidates.iter().enumerate() {
+        if messenger.is_stopped() {
+            return Vec::new();
+        }
+        messenger.set_progress(candidates.len(), count + 1, "Compare audio fingerprints...");
+        if compare_fingerprints(&fingerprints[*i].1, &fingerprints[*j].1) >= options.acoustic_threshold {
+            pairs.push((*i, *j));
+        }
+    }
+
+    let mut groups: Vec<Vec<&(&FileInfo, Vec<u32>)>> = cluster(fingerprints.len(), &pairs)
+        .into_iter()
+        .map(|members| members.iter().map(|i| &fingerprints[*i]).collect())
+        .collect();
+    for group in groups.iter_mut() {
+        group.sort_by(|a, b| a.0.path().cmp(b.0.path()));
+    }
+    groups.sort_by(|a, b| a[0].0.path().cmp(b[0].0.path()));
+
+    let mut group_id = 0;
     let mut duplicates: Vec<HashMap<String, String>> = Vec::new();
+    for group in groups.iter() {
+        let members: Vec<&FileInfo> = group.iter().map(|(fi, _)| *fi).collect();
+        let results = group_results(&members, reference_mode, group_id + 1, options.integrity_check, messenger);
+        if results.is_empty() {
+            continue;
+        }
+        group_id += 1;
+
+        let first = &group.iter().find(|(fi, _)| fi.reference).unwrap_or(&group[0]).1;
+        for mut map in results {
+            let fingerprint = &group.iter().find(|(fi, _)| fi.path_to_str() == map["PATH"]).unwrap().1;
+            let score = compare_fingerprints(first, fingerprint);
+            map.insert("SCORE".to_string(), score.to_string());
+            map.insert("NOTE".to_string(), format!("Acoustic match ({}% similar)", score));
+            duplicates.push(map.clone());
+            messenger.push_reslog(map);
+        }
+    }
+    duplicates
+}
+
+/// Union-Find over the given pairs of indices. Returns the clusters with two or more members.
+fn cluster(len: usize, pairs: &[(usize, usize)]) -> Vec<Vec<usize>> {
+    fn find(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+
+    let mut parent: Vec<usize> = (0..len).collect();
+    for (i, j) in pairs.iter() {
+        let (root_i, root_j) = (find(&mut parent, *i), find(&mut parent, *j));
+        parent[root_j] = root_i;
+    }
+
+    let mut clusters: HashMap<usize, Vec<usize>> = HashMap::new();
+    for i in 0..len {
+        let root = find(&mut parent, i);
+        clusters.entry(root).or_default().push(i);
+    }
+    clusters.into_values().filter(|members| members.len() > 1).collect()
+}
+
+/// Split the files of one bucket into groups of identical files.
+///
+/// Only groups with two or more files are returned. The members are sorted by path.
+fn find_duplicates<'a>(
+    scan_type: &ScanType,
+    file_infos: &'a [FileInfo],
+    options: &ScanOptions,
+    messenger: &Messenger,
+) -> Vec<Vec<&'a FileInfo>> {
+    if *scan_type == ScanType::METADATA {
+        return find_metadata_duplicates(file_infos, options, messenger);
+    }
+
+    let mut groups: HashMap<String, Vec<&FileInfo>> = HashMap::new();
+
+    for file_info in file_infos.iter() {
+        if messenger.is_stopped() {
+            break;
+        }
 
-    for i in 0..file_infos.len() - 1 {
-        let file_info1 = &file_infos[i];
-        for j in i + 1..file_infos.len() {
-            let file_info2 = &file_infos[j];
-            let mut insert = false;
-
-            match scan_type {
-                ScanType::BINARY => {
-                    // compare files with identical headers
-                    if file_info1.checksum == 0 || file_info2.checksum == 0 {
-                        messenger.push_errlog(format!("No checksum for file {}", file_info1.path_to_str()));
+        let key = match scan_type {
+            ScanType::AUDIO_PAYLOAD => {
+                // compare only the audio stream, tags are ignored
+                match get_audio_payload(file_info.path()).and_then(|ranges| compute_payload_checksum(file_info.path(), &ranges)) {
+                    Ok(checksum) => checksum,
+                    Err(e) => {
+                        messenger.push_errlog(format!("Error for file {:?} : {:?}", file_info.path(), e.to_string()));
                         continue;
                     }
-                    // Botch checksums are equal
-                    if file_info1.checksum - file_info2.checksum == 0 {
-                        let get_checksum = |path: &Path, messenger: &Messenger| -> String {
-                            match compute_file_checksum(path) {
-                                Ok(checksum) => checksum,
-                                Err(e) => {
-                                    messenger.push_errlog(format!("Error for file {:?} : {:?}", path, e.to_string()));
-                                    String::new()
-                                }
-                            }
-                        };
-                        insert = get_checksum(file_info1.path(), &messenger) == get_checksum(file_info2.path(), &messenger);
-                    }
                 }
-                ScanType::METADATA => {
-                    let unwrap = |path: &Path| -> String {
-                        match get_audio_key(path) {
-                            Ok(key) => key,
-                            Err(e) => {
-                                messenger.push_errlog(format!("Error Could not get Key file {:?} : {:?}", file_info1.path(), e.to_string()));
-                                String::new()
-                            }
-                        }
-                    };
-
-                    let key1 = unwrap(file_info1.path());
-                    let key2 = unwrap(file_info2.path());
-                    insert = key1 == key2;
+            }
+            _ => {
+                // compare files with identical headers
+                if file_info.checksum == 0 {
+                    messenger.push_errlog(format!("No checksum for file {}", file_info.path_to_str()));
+                    continue;
                 }
-            };
+                match compute_file_checksum(file_info.path()) {
+                    Ok(checksum) => format!("{}:{}", file_info.checksum, checksum),
+                    Err(e) => {
+                        messenger.push_errlog(format!("Error for file {:?} : {:?}", file_info.path(), e.to_string()));
+                        continue;
+                    }
+                }
+            }
+        };
+        groups.entry(key).or_default().push(file_info);
+    }
+
+    let mut groups: Vec<Vec<&FileInfo>> = groups.into_values().filter(|g| g.len() > 1).collect();
+    for group in groups.iter_mut() {
+        group.sort_by(|a, b| a.path().cmp(b.path()));
+    }
+    groups
+}
 
-            if insert {
-                duplicates.push(get_audio_tags(file_info1.path()).unwrap());
-                duplicates.push(get_audio_tags(file_info2.path()).unwrap());
+/// Split the files of one METADATA bucket into groups of matching files.
+///
+/// The files are sorted by duration, so each file is only compared with the files inside the
+/// duration tolerance. Matches are chained, so a group can hold files which only match
+/// through another member.
+fn find_metadata_duplicates<'a>(file_infos: &'a [FileInfo], options: &ScanOptions, messenger: &Messenger) -> Vec<Vec<&'a FileInfo>> {
+    let tags: Vec<&HashMap<String, String>> = file_infos.iter().map(|fi| &fi.tags).collect();
+    let mut groups: Vec<Vec<&FileInfo>> = metadata_clusters(&tags, options, || messenger.is_stopped())
+        .into_iter()
+        .map(|members| members.iter().map(|i| &file_infos[*i]).collect())
+        .collect();
+    for group in groups.iter_mut() {
+        group.sort_by(|a, b| a.path().cmp(b.path()));
+    }
+    groups
+}
+
+/// Split files into groups of files with matching tags, returned as indices into 'tags'.
+///
+/// With 'musicbrainz_match' two files which both have a MusicBrainz ID match if the IDs are
+/// equal, different IDs never match. Otherwise two files match if the match profile matches
+/// their values and durations. Returns no groups if 'is_stopped'.
+pub fn metadata_clusters(tags: &[&HashMap<String, String>], options: &ScanOptions, is_stopped: impl Fn() -> bool) -> Vec<Vec<usize>> {
+    let profile = &options.match_profile;
+    let tolerance_ms = (options.duration_tolerance * 1000.0) as u64;
+    let threshold = options.fuzzy_threshold as f64 / 100.0;
+
+    let ids: Vec<Option<String>> = tags.iter().map(|tags| options.musicbrainz_key(tags)).collect();
+    let mut entries: Vec<(usize, Vec<String>, Option<u64>)> = tags
+        .iter()
+        .enumerate()
+        .filter_map(|(i, tags)| profile.values(tags, &options.normalize_rules).ok().map(|values| (i, values, duration_ms(tags))))
+        .collect();
+    entries.sort_by_key(|(_, _, ms)| ms.unwrap_or(0));
+
+    // Durations with equal seconds match if there is no tolerance
+    let window = if profile.has_duration() { tolerance_ms.max(1000) } else { u64::MAX };
+
+    let mut pairs: Vec<(usize, usize)> = Vec::new();
+    for i in 0..entries.len() {
+        if is_stopped() {
+            return Vec::new();
+        }
+        for j in i + 1..entries.len() {
+            if let (Some(ms_i), Some(ms_j)) = (entries[i].2, entries[j].2) {
+                if ms_j - ms_i > window {
+                    break;
+                }
+            }
+            let (a, b) = (&entries[i], &entries[j]);
+            if ids[a.0].is_some() && ids[b.0].is_some() {
+                continue; // Matched by the ID below
+            }
+            if profile.is_match((&a.1, a.2), (&b.1, b.2), tolerance_ms, threshold) {
+                pairs.push((a.0, b.0));
             }
+        }
+    }
 
-            if messenger.is_stopped() {
-                break;
+    // Files with the same MusicBrainz ID match regardless of the profile
+    let mut first_with_id: HashMap<&String, usize> = HashMap::new();
+    for (i, id) in ids.iter().enumerate() {
+        if let Some(id) = id {
+            let first = *first_with_id.entry(id).or_insert(i);
+            if first != i {
+                pairs.push((first, i));
             }
         }
     }
-    duplicates
+
+    cluster(tags.len(), &pairs)
+}
+
+/// Describes which key matched the files of a METADATA group
+fn matched_by(group: &[&FileInfo], options: &ScanOptions) -> String {
+    let ids: HashSet<Option<String>> = group.iter().map(|fi| options.musicbrainz_key(&fi.tags)).collect();
+    if ids.len() == 1 && !ids.contains(&None) {
+        if options.musicbrainz_release {
+            "MusicBrainz recording, release and track ID".to_string()
+        } else {
+            "MusicBrainz recording ID".to_string()
+        }
+    } else if options.is_fuzzy() {
+        format!("{} ({}% similar)", options.match_profile.name, options.fuzzy_threshold)
+    } else {
+        options.match_profile.name.clone()
+    }
+}
+
+/// Build the result maps for the members of a group.
+///
+/// In reference mode only the candidates of groups with a reference copy are returned, with
+/// the path of the reference copy in 'REFERENCE'. Audio files get their 'QUALITY' and a
+/// 'QUALITY_RANK' for the auto-marking. Groups whose members have different encodings or
+/// extensions are flagged in 'NOTE'. With 'integrity' the audio files are decoded and get
+/// their problems or 'OK' in 'INTEGRITY'. Decoding ends when the scan is stopped, the
+/// remaining files get no 'INTEGRITY' then.
+fn group_results(
+    group: &[&FileInfo],
+    reference_mode: bool,
+    group_id: usize,
+    integrity: bool,
+    messenger: &Messenger,
+) -> Vec<HashMap<String, String>> {
+    let reference: Option<&FileInfo> = group.iter().find(|fi| fi.reference).copied();
+    if reference_mode && reference.is_none() {
+        return Vec::new();
+    }
+
+    let maps: Vec<HashMap<String, String>> = group
+        .iter()
+        .map(|fi| if fi.tags.is_empty() { get_result_map(fi.path()) } else { fi.tags.clone() })
+        .collect();
+    let qualities: Vec<Option<(String, String)>> = maps.iter().map(get_quality).collect();
+    let integrities: Vec<Option<String>> = group
+        .iter()
+        .zip(maps.iter())
+        .map(|(fi, map)| {
+            let audio = integrity && map.contains_key("Format");
+            let duration_ms = map.get("DurationMs").and_then(|ms| ms.parse().ok());
+            audio.then(|| get_integrity(fi.path(), duration_ms, || messenger.is_stopped())).flatten()
+        })
+        .collect();
+
+    let extensions: HashSet<&str> = group.iter().map(|fi| fi.extension.as_str()).collect();
+    let formats: HashSet<&str> = maps.iter().filter_map(|m| m.get("Format").map(|f| f.as_str())).collect();
+    let lossless: HashSet<bool> = qualities.iter().flatten().map(|(label, _)| label.starts_with("Lossless")).collect();
+    let note = if lossless.len() > 1 {
+        Some("Same recording, lossless and lossy")
+    } else if formats.len() > 1 {
+        Some("Different encodings")
+    } else if extensions.len() > 1 {
+        Some("Different extensions")
+    } else {
+        None
+    };
+    let reference_rank = group
+        .iter()
+        .position(|fi| reference_mode && fi.reference)
+        .and_then(|i| qualities[i].as_ref().map(|(_, rank)| rank.clone()));
+    let reference_integrity = group
+        .iter()
+        .position(|fi| reference_mode && fi.reference)
+        .and_then(|i| integrities[i].clone());
+    let reference_cover = group
+        .iter()
+        .position(|fi| reference_mode && fi.reference)
+        .map(|i| maps[i].get("CoverPixels").cloned().unwrap_or_else(|| "0".to_string()));
+
+    let mut results = Vec::new();
+    for (((file_info, mut map), quality), integrity) in group.iter().zip(maps).zip(qualities).zip(integrities) {
+        if reference_mode && file_info.reference {
+            continue;
+        }
+        map.insert("GROUP".to_string(), group_id.to_string());
+        if let (true, Some(reference)) = (reference_mode, reference) {
+            map.insert("REFERENCE".to_string(), reference.path_to_str().to_string());
+        }
+        if let Some((label, rank)) = quality {
+            map.insert("QUALITY".to_string(), label);
+            map.insert("QUALITY_RANK".to_string(), rank);
+        }
+        if let Some(rank) = &reference_rank {
+            map.insert("REFERENCE_QUALITY_RANK".to_string(), rank.clone());
+        }
+        if let Some(integrity) = integrity {
+            map.insert("INTEGRITY".to_string(), integrity);
+        }
+        if let Some(integrity) = &reference_integrity {
+            map.insert("REFERENCE_INTEGRITY".to_string(), integrity.clone());
+        }
+        if let Some(pixels) = &reference_cover {
+            map.insert("REFERENCE_COVER_PIXELS".to_string(), pixels.clone());
+        }
+        if let Some(note) = note {
+            map.insert("NOTE".to_string(), note.to_string());
+        }
+        results.push(map);
+    }
+    results
+}
+
+/// Returns the tags of the file, or only its 'PATH' if the file has no readable tags
+fn get_result_map(path: &Path) -> HashMap<String, String> {
+    match get_audio_tags(path) {
+        Ok(map) => map,
+        Err(_) => HashMap::from([("PATH".to_string(), path.to_str().unwrap().to_string())]),
+    }
 }
 
 fn create_bash_script(
@@ -243,13 +921,22 @@ fn create_bash_script(
 struct FileInfo {
     dir_entry: DirEntry,
     checksum: isize,
+    reference: bool,   // File is inside the reference directory
+    extension: String, // Extension from the file name, i.e. '.JPG'
+    file_type: String, // Format detected by content, or the extension if not detected
+    tags: HashMap<String, String>, // Tags and properties, read by the METADATA scan
 }
 
 impl FileInfo {
-    pub fn new(dir_entry: DirEntry) -> FileInfo {
+    pub fn new(dir_entry: DirEntry, reference: bool) -> FileInfo {
+        let extension = get_extension(dir_entry.path().to_str().unwrap());
         FileInfo {
             dir_entry,
             checksum: 0,
+            reference,
+            file_type: extension.clone(),
+            extension,
+            tags: HashMap::new(),
         }
     }
 
@@ -263,14 +950,73 @@ impl FileInfo {
 
     /// Returns a String as key for the hashmap.
     ///
-    /// Key is build by combining the file length with the extension.
-    /// If no extension is found, then only the file length will be used as key
-    pub fn get_key(&self, length: u64) -> String {
-        let path = self.path_to_str();
-        let key = match path.rfind('.') {
-            Some(idx) => format!("{}{}", length, &path[idx..].to_uppercase()),
-            None => format!("{}", length),
-        };
-        key
+    /// Key is the file length, so files are matched by content only. With 'same_type' the
+    /// detected file type is added, so only files of the same type are compared.
+    pub fn get_key(&self, length: u64, same_type: bool) -> String {
+        if same_type {
+            format!("{}:{}", length, self.file_type)
+        } else {
+            length.to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(title: &str, ms: u64, id: Option<&str>) -> HashMap<String, String> {
+        let mut tags = HashMap::new();
+        tags.insert("TrackTitle".to_string(), title.to_string());
+        tags.insert("TrackArtist".to_string(), "Artist".to_string());
+        tags.insert("AlbumTitle".to_string(), "Album".to_string());
+        tags.insert("Duration".to_string(), (ms / 1000).to_string());
+        tags.insert("DurationMs".to_string(), ms.to_string());
+        if let Some(id) = id {
+            tags.insert("MusicBrainzRecordingId".to_string(), id.to_string());
+        }
+        tags
+    }
+
+    fn clusters(tracks: &[HashMap<String, String>]) -> Vec<Vec<usize>> {
+        let mut options = ScanOptions::new();
+        options.prepare().unwrap();
+        let tags: Vec<&HashMap<String, String>> = tracks.iter().collect();
+        let mut clusters = metadata_clusters(&tags, &options, || false);
+        clusters.iter_mut().for_each(|cluster| cluster.sort());
+        clusters.sort();
+        clusters
+    }
+
+    #[test]
+    fn cluster_joins_transitive_pairs() {
+        let mut clusters = cluster(6, &[(0, 1), (1, 2), (4, 5)]);
+        clusters.iter_mut().for_each(|cluster| cluster.sort());
+        clusters.sort();
+        assert_eq!(clusters, vec![vec![0, 1, 2], vec![4, 5]]);
+        assert!(cluster(3, &[]).is_empty());
+    }
+
+    #[test]
+    fn different_musicbrainz_ids_never_match() {
+        let tracks = [track("Song", 200_000, Some("live")), track("Song", 200_000, Some("studio"))];
+        assert!(clusters(&tracks).is_empty());
+    }
+
+    #[test]
+    fn equal_musicbrainz_ids_match_despite_the_tags() {
+        let tracks = [track("Song", 200_000, Some("id")), track("Other title", 300_000, Some("ID"))];
+        assert_eq!(clusters(&tracks), vec![vec![0, 1]]);
+    }
+
+    #[test]
+    fn files_without_id_match_by_the_profile() {
+        let tracks = [
+            track("Song", 200_000, Some("id")),
+            track("Song", 200_400, None),
+            track("Song", 205_000, None),
+            track("Other", 200_000, None),
+        ];
+        assert_eq!(clusters(&tracks), vec![vec![0, 1]]);
     }
 }
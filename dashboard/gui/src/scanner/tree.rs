@@ -0,0 +1,297 @@
+use crate::scanner::messenger::Messenger;
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+use dashboard_common::version::hash_string;
+
+/// A file with the digest of its content
+pub struct TreeFile<'a> {
+    pub path: &'a Path,
+    pub digest: String,
+    pub reference: bool, // File is inside the reference directory
+}
+
+#[derive(Default)]
+struct DirNode {
+    files: Vec<(String, String)>, // (file name, content digest) of the files in this directory
+    subdirs: HashSet<PathBuf>,
+    file_count: usize, // Files in the whole subtree
+    reference: bool,
+    digest: u64, // Merkle digest over the names and digests of files and subdirectories
+}
+
+/// Find directories with identical content and directories whose files are a subset of
+/// another directory.
+///
+/// Each directory is reported as one row. A subtree of an identical directory is only
+/// reported if it has a copy outside of the identical parents.
+pub fn find_duplicate_dirs(
+    files: &[TreeFile],
+    roots: &[&Path],
+    reference_mode: bool,
+    messenger: &Messenger,
+) -> Vec<HashMap<String, String>> {
+    messenger.set_progress(3, 1, "Calculate directory digests...");
+    let nodes = build_tree(files, roots);
+
+    let mut duplicates = Vec::new();
+    let mut group_id = 0;
+
+    messenger.set_progress(3, 2, "Check for identical directories...");
+    for group in identical_dirs(&nodes) {
+        let reference = group.iter().find(|d| nodes[**d].reference);
+        if reference_mode && reference.is_none() {
+            continue;
+        }
+
+        group_id += 1;
+        for dir in group.iter().filter(|d| !(reference_mode && nodes[**d].reference)) {
+            let node = &nodes[*dir];
+            let mut map = dir_result_map(dir, node, group_id);
+            map.insert("NOTE".to_string(), format!("Identical directory ({} files)", node.file_count));
+            if reference_mode {
+                map.insert("REFERENCE".to_string(), reference.unwrap().to_string_lossy().to_string());
+            }
+            duplicates.push(map);
+        }
+    }
+
+    messenger.set_progress(3, 3, "Check for subset directories...");
+    let mut supersets: Vec<(&PathBuf, Vec<&PathBuf>)> = subset_dirs(&nodes, reference_mode).into_iter().collect();
+    supersets.sort();
+    for (superset, subsets) in supersets {
+        group_id += 1;
+        let total = nodes[superset].files.len();
+        if !reference_mode {
+            let mut map = dir_result_map(superset, &nodes[superset], group_id);
+            map.insert("NOTE".to_string(), format!("Superset ({} files)", total));
+            duplicates.push(map);
+        }
+        for subset in subsets {
+            let node = &nodes[subset];
+            let mut map = dir_result_map(subset, node, group_id);
+            map.insert("NOTE".to_string(), format!("Subset ({} of {} files)", node.files.len(), total));
+            if reference_mode {
+                map.insert("REFERENCE".to_string(), superset.to_string_lossy().to_string());
+            }
+            duplicates.push(map);
+        }
+    }
+
+    duplicates
+}
+
+/// Build the directory nodes and calculate the digests bottom up
+fn build_tree(files: &[TreeFile], roots: &[&Path]) -> HashMap<PathBuf, DirNode> {
+    let mut nodes: HashMap<PathBuf, DirNode> = HashMap::new();
+
+    for file in files.iter() {
+        let dir = match file.path.parent() {
+            Some(dir) => dir,
+            None => continue,
+        };
+        let name = file.path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let node = nodes.entry(dir.to_path_buf()).or_default();
+        node.files.push((name, file.digest.clone()));
+        node.reference = file.reference;
+
+        // Link the directory into its parents up to the root
+        let mut child = dir;
+        while !roots.contains(&child) {
+            let parent = match child.parent() {
+                Some(parent) => parent,
+                None => break,
+            };
+            let parent_node = nodes.entry(parent.to_path_buf()).or_default();
+            parent_node.reference = file.reference;
+            if !parent_node.subdirs.insert(child.to_path_buf()) {
+                break; // Already linked, so are the parents
+            }
+            child = parent;
+        }
+    }
+
+    // Deepest directories first, so the digests of all subdirectories are known
+    let mut dirs: Vec<PathBuf> = nodes.keys().cloned().collect();
+    dirs.sort_by_key(|d| std::cmp::Reverse(d.components().count()));
+
+    for dir in dirs.iter() {
+        let node = &nodes[dir];
+        let mut entries: Vec<String> = node.files.iter().map(|(name, digest)| format!("f:{}:{}", name, digest)).collect();
+        let mut file_count = node.files.len();
+        for subdir in node.subdirs.iter() {
+            let sub_node = &nodes[subdir];
+            let name = subdir.file_name().unwrap_or_default().to_string_lossy();
+            entries.push(format!("d:{}:{}", name, sub_node.digest));
+            file_count += sub_node.file_count;
+        }
+        entries.sort();
+
+        let node = nodes.get_mut(dir).unwrap();
+        node.digest = hash_string(&entries.join("/"));
+        node.file_count = file_count;
+    }
+    nodes
+}
+
+/// Returns groups of directories with the same digest.
+///
+/// Groups are skipped if all members are inside directories which are identical themselves.
+fn identical_dirs(nodes: &HashMap<PathBuf, DirNode>) -> Vec<Vec<&PathBuf>> {
+    let mut by_digest: HashMap<u64, Vec<&PathBuf>> = HashMap::new();
+    for (dir, node) in nodes.iter() {
+        if node.file_count > 0 {
+            by_digest.entry(node.digest).or_default().push(dir);
+        }
+    }
+
+    let is_duplicated = |dir: &Path| {
+        nodes.get(dir).is_some_and(|node| by_digest.get(&node.digest).is_some_and(|group| group.len() > 1))
+    };
+
+    let mut groups: Vec<Vec<&PathBuf>> = by_digest
+        .values()
+        .filter(|group| group.len() > 1)
+        .filter(|group| !group.iter().all(|d| d.parent().is_some_and(is_duplicated)))
+        .cloned()
+        .collect();
+    for group in groups.iter_mut() {
+        group.sort();
+    }
+    groups.sort();
+    groups
+}
+
+/// Returns the directories whose files are a proper subset of the files of another directory,
+/// grouped by the smallest superset. Only the files directly inside a directory are compared.
+fn subset_dirs(nodes: &HashMap<PathBuf, DirNode>, reference_mode: bool) -> HashMap<&PathBuf, Vec<&PathBuf>> {
+    let digests = |node: &DirNode| -> HashSet<String> { node.files.iter().map(|(_, digest)| digest.clone()).collect() };
+
+    // Directories containing a digest
+    let mut index: HashMap<String, Vec<&PathBuf>> = HashMap::new();
+    for (dir, node) in nodes.iter() {
+        for digest in digests(node) {
+            index.entry(digest).or_default().push(dir);
+        }
+    }
+
+    let mut supersets: HashMap<&PathBuf, Vec<&PathBuf>> = HashMap::new();
+    for (dir, node) in nodes.iter() {
+        let files = digests(node);
+        if files.len() < 2 || (reference_mode && node.reference) {
+            continue;
+        }
+
+        // Only directories containing the rarest file can be a superset
+        let rarest = files.iter().min_by_key(|digest| index[*digest].len()).unwrap();
+        let mut best: Option<(&PathBuf, usize)> = None;
+        for other in index[rarest].iter() {
+            if *other == dir || dir.starts_with(other) || other.starts_with(dir) {
+                continue;
+            }
+            if reference_mode && !nodes[*other].reference {
+                continue;
+            }
+            let other_files = digests(&nodes[*other]);
+            if other_files.len() > files.len()
+                && files.is_subset(&other_files)
+                && best.map_or(true, |(_, len)| other_files.len() < len)
+            {
+                best = Some((other, other_files.len()));
+            }
+        }
+
+        if let Some((superset, _)) = best {
+            supersets.entry(superset).or_default().push(dir);
+        }
+    }
+    for subsets in supersets.values_mut() {
+        subsets.sort();
+    }
+    supersets
+}
+
+fn dir_result_map(dir: &Path, node: &DirNode, group_id: usize) -> HashMap<String, String> {
+    HashMap::from([
+        ("PATH".to_string(), dir.to_string_lossy().to_string()),
+        ("GROUP".to_string(), group_id.to_string()),
+        ("FILES".to_string(), node.file_count.to_string()),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files<'a>(paths: &'a [PathBuf], digests: &[&str], reference: &str) -> Vec<TreeFile<'a>> {
+        paths
+            .iter()
+            .zip(digests.iter())
+            .map(|(path, digest)| TreeFile {
+                path: path.as_path(),
+                digest: digest.to_string(),
+                reference: !reference.is_empty() && path.starts_with(reference),
+            })
+            .collect()
+    }
+
+    fn paths(names: &[&str]) -> Vec<PathBuf> {
+        names.iter().map(PathBuf::from).collect()
+    }
+
+    fn notes(results: &[HashMap<String, String>]) -> Vec<(String, String, String)> {
+        let mut notes: Vec<(String, String, String)> =
+            results.iter().map(|m| (m["GROUP"].clone(), m["PATH"].clone(), m["NOTE"].clone())).collect();
+        notes.sort();
+        notes
+    }
+
+    #[test]
+    fn identical_subtrees_are_reported_by_their_parents() {
+        let paths = paths(&["/r/a/1", "/r/a/s/2", "/r/b/1", "/r/b/s/2", "/r/c/3"]);
+        let files = files(&paths, &["x", "y", "x", "y", "z"], "");
+        let results = find_duplicate_dirs(&files, &[Path::new("/r")], false, &Messenger::new());
+        assert_eq!(
+            notes(&results),
+            vec![
+                ("1".to_string(), "/r/a".to_string(), "Identical directory (2 files)".to_string()),
+                ("1".to_string(), "/r/b".to_string(), "Identical directory (2 files)".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn subset_is_grouped_by_the_smallest_superset() {
+        let paths = paths(&["/r/a/1", "/r/a/2", "/r/b/1", "/r/b/2", "/r/b/3", "/r/c/1", "/r/c/2", "/r/c/3", "/r/c/4"]);
+        let files = files(&paths, &["x", "y", "x", "y", "z", "x", "y", "z", "w"], "");
+        let nodes = build_tree(&files, &[Path::new("/r")]);
+        let supersets = subset_dirs(&nodes, false);
+
+        let (a, b, c) = (PathBuf::from("/r/a"), PathBuf::from("/r/b"), PathBuf::from("/r/c"));
+        assert_eq!(supersets.len(), 2);
+        assert_eq!(supersets[&b], vec![&a]);
+        assert_eq!(supersets[&c], vec![&b]);
+    }
+
+    #[test]
+    fn equal_file_sets_and_parents_are_no_subsets() {
+        // Same digests under other names are no proper subset, the parent holds no files itself
+        let paths = paths(&["/r/a/1", "/r/a/2", "/r/b/3", "/r/b/4", "/r/b/c/1"]);
+        let files = files(&paths, &["x", "y", "x", "y", "x"], "");
+        let nodes = build_tree(&files, &[Path::new("/r")]);
+        assert!(subset_dirs(&nodes, false).is_empty());
+    }
+
+    #[test]
+    fn reference_mode_only_reports_subsets_of_the_reference() {
+        let paths = paths(&["/ref/a/1", "/ref/a/2", "/ref/a/3", "/lib/a/1", "/lib/a/2", "/lib/b/1", "/lib/b/2", "/lib/b/3", "/lib/b/4"]);
+        let files = files(&paths, &["x", "y", "z", "x", "y", "x", "y", "z", "w"], "/ref");
+        let results = find_duplicate_dirs(&files, &[Path::new("/ref"), Path::new("/lib")], true, &Messenger::new());
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0]["PATH"], "/lib/a");
+        assert_eq!(results[0]["REFERENCE"], "/ref/a");
+        assert_eq!(results[0]["NOTE"], "Subset (2 of 3 files)");
+    }
+}